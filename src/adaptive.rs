@@ -0,0 +1,104 @@
+use std::collections::HashMap;
+
+use crate::{history::History, stats::KeyStats};
+
+/// Number of most recent sessions taken into account
+const RECENT_SESSIONS: usize = 50;
+
+/// Keys and bigrams typed fewer times than this are considered unknown
+const MIN_PRESSES: u32 = 5;
+
+/// How much miss rate matters compared to latency
+const MISS_RATE_FACTOR: f64 = 4.0;
+
+/// Weight multiplier of a word made only of the weakest letters and bigrams
+const MAX_BOOST: f64 = 100.0;
+
+/// Weakness scores of letters and bigrams in range 0.0..=1.0, 1.0 being the worst
+#[derive(Debug, Default)]
+pub struct Weakness {
+    keys: HashMap<char, f64>,
+    bigrams: HashMap<String, f64>,
+}
+
+impl Weakness {
+    /// Aggregates per key and per bigram stats of recent sessions in the history
+    pub fn from_history(history: &History) -> Self {
+        let mut keys: HashMap<char, KeyStats> = HashMap::new();
+        let mut bigrams: HashMap<String, KeyStats> = HashMap::new();
+
+        for record in history.records.iter().rev().take(RECENT_SESSIONS) {
+            for (key, stats) in &record.keys {
+                *keys.entry(*key).or_default() += *stats;
+            }
+            for (bigram, stats) in &record.bigrams {
+                *bigrams.entry(bigram.clone()).or_default() += *stats;
+            }
+        }
+
+        Self {
+            keys: scores(keys),
+            bigrams: scores(bigrams),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty() && self.bigrams.is_empty()
+    }
+
+    /// Average weakness of the letters and bigrams a word is made of
+    pub fn of_word(&self, word: &str) -> f64 {
+        let chars: Vec<char> = word.chars().flat_map(|c| c.to_lowercase()).collect();
+
+        let key_scores = chars.iter().filter_map(|c| self.keys.get(c));
+        let bigram_scores = chars
+            .windows(2)
+            .filter_map(|pair| self.bigrams.get(&pair.iter().collect::<String>()));
+
+        let (sum, count) = key_scores
+            .chain(bigram_scores)
+            .fold((0.0, 0), |(sum, count), score| (sum + score, count + 1));
+
+        if count == 0 { 0.0 } else { sum / count as f64 }
+    }
+
+    /// Multiplier applied to the frequency weight of a word
+    pub fn boost(&self, word: &str) -> f64 {
+        (1.0 + (MAX_BOOST.sqrt() - 1.0) * self.of_word(word)).powi(2)
+    }
+}
+
+/// Combines miss rate and latency relative to the average into a normalized score
+fn scores<K: std::hash::Hash + Eq>(stats: HashMap<K, KeyStats>) -> HashMap<K, f64> {
+    let stats: Vec<_> = stats
+        .into_iter()
+        .filter(|(_, s)| s.presses >= MIN_PRESSES)
+        .collect();
+
+    let mut total = KeyStats::default();
+    for (_, s) in &stats {
+        total += *s;
+    }
+    let average_latency = total.average_latency().as_secs_f64();
+
+    let raw: Vec<_> = stats
+        .into_iter()
+        .map(|(key, s)| {
+            let slowness = if average_latency > 0.0 {
+                (s.average_latency().as_secs_f64() / average_latency - 1.0).max(0.0)
+            } else {
+                0.0
+            };
+            (key, MISS_RATE_FACTOR * s.miss_rate() + slowness)
+        })
+        .collect();
+
+    let worst = raw.iter().map(|(_, score)| *score).fold(0.0, f64::max);
+    if worst == 0.0 {
+        return HashMap::new();
+    }
+
+    raw.into_iter()
+        .map(|(key, score)| (key, score / worst))
+        .collect()
+}
@@ -1,79 +1,256 @@
+use color_eyre::Result;
 use crossterm::event::{KeyCode, KeyModifiers};
+use rand::rngs::StdRng;
 use ratatui::{
     Frame,
     layout::{Constraint, Layout},
-    style::{Color, Modifier, Style},
+    style::Modifier,
     text::{Line, Span},
-    widgets::Paragraph,
+    widgets::{Block, Paragraph},
 };
 
-use crate::{character::Character, config::Config, state::State};
+use crate::{
+    character::Character,
+    config::{Config, Rule, StatusBar},
+    ghost::Ghost,
+    heatmap::{HeatmapView, render_keyboard},
+    state::State,
+    stats::key_stats,
+    syntax::TokenClass,
+    text::{Generator, Width},
+    theme::Theme,
+    viewport::Viewport,
+};
 
 #[derive(Debug)]
 pub struct App<'a> {
     pub config: &'a Config,
+    pub theme: &'a Theme,
     pub state: State,
     pub should_quit: bool,
+    pub heatmap_view: HeatmapView,
+    pub ghost: Option<Ghost>,
+    pub rng: StdRng,
+
+    /// Draws more text for timed sessions
+    pub generator: Option<Generator>,
 }
 
 impl<'a> App<'a> {
-    pub fn new(state: State, config: &'a Config) -> Self {
+    pub fn new(state: State, config: &'a Config, theme: &'a Theme, rng: StdRng) -> Self {
         Self {
             config,
+            theme,
             state,
             should_quit: false,
+            heatmap_view: HeatmapView::Errors,
+            ghost: None,
+            rng,
+            generator: None,
         }
     }
 
-    fn render_character(&self, character: &Character) -> Span<'_> {
+    fn render_character(&self, character: &Character, class: TokenClass) -> Span<'_> {
         match character {
-            Character::Hit(c) => Span::raw(c.to_string()),
+            Character::Hit(c) => Span::styled(c.to_string(), self.theme.hit),
             Character::Miss(c) => Span::styled(
                 if *c == ' ' {
                     '_'.to_string()
                 } else {
                     c.to_string()
                 },
-                Style::default()
-                    .fg(Color::Indexed(self.config.fg_miss))
-                    .add_modifier(Modifier::BOLD),
+                self.theme.miss,
             ),
             Character::Empty(c) => Span::styled(
                 c.to_string(),
-                Style::default().fg(Color::Indexed(self.config.fg_empty)),
+                self.theme.empty.patch(self.theme.token(class)),
             ),
         }
     }
 
-    pub fn draw(&self, frame: &mut Frame<'_>) {
+    fn render_status(&self) -> Line<'_> {
+        let elapsed = self.state.elapsed_seconds() as u64;
+        let status = format!(
+            "{:.0} wpm ~ {:.0}% accuracy ~ {}:{:02} ~ {:.0}%",
+            self.state.wpm(),
+            self.state.accuracy() * 100.0,
+            elapsed / 60,
+            elapsed % 60,
+            self.state.progress() * 100.0,
+        );
+
+        Line::from(Span::styled(status, self.theme.status))
+    }
+
+    fn render_page(&self) -> Vec<Line<'_>> {
         let page = self.state.build_page();
+        let annotations = self.state.build_annotations();
+
+        let ghost_cursor = match &self.ghost {
+            Some(ghost) if !self.state.is_complete() => Some(ghost.cursor(self.state.elapsed())),
+            _ => None,
+        };
+        let cursor = (!self.state.is_complete()).then(|| self.state.cursor());
 
         let mut lines = vec![];
-        for line in page.iter() {
-            let spans: Vec<_> = line
+        for (row, line) in page.iter().enumerate() {
+            let classes = annotations.get(row);
+            let mut spans: Vec<_> = line
                 .iter()
-                .map(|character| self.render_character(character))
+                .enumerate()
+                .map(|(col, character)| {
+                    let class = classes
+                        .and_then(|classes| classes.get(col))
+                        .copied()
+                        .unwrap_or(TokenClass::Plain);
+                    self.render_character(character, class)
+                })
                 .collect();
+
+            if let Some((ghost_row, ghost_col)) = ghost_cursor
+                && ghost_row == row
+            {
+                match spans.get_mut(ghost_col) {
+                    Some(span) => span.style = span.style.patch(self.theme.ghost),
+                    None => spans.push(Span::styled(" ", self.theme.ghost)),
+                }
+            }
+
+            if let Some((cursor_row, cursor_col)) = cursor
+                && cursor_row == row
+                && let Some(span) = spans.get_mut(cursor_col)
+            {
+                span.style = span.style.patch(self.theme.cursor);
+            }
+
             lines.push(Line::from(spans));
         }
 
-        if self.state.is_complete() {
-            let wpm = self.state.wpm();
-            let accuracy = self.state.accuracy() * 100.0;
+        lines
+    }
 
-            let results = format!("{:.0} words per minute ~ {:.0}% accuracy", wpm, accuracy);
-            lines.push(Line::from(""));
+    fn render_results(&self) -> Vec<Line<'_>> {
+        let mut lines = vec![];
+
+        let wpm = self.state.wpm();
+        let accuracy = self.state.accuracy() * 100.0;
+
+        let results = format!("{:.0} words per minute ~ {:.0}% accuracy", wpm, accuracy);
+        lines.push(Line::from(""));
+        lines.push(Line::from(Span::styled(
+            results,
+            self.theme.results,
+        )));
+
+        let details = format!(
+            "{:.0} raw wpm ~ {:.0} net wpm ~ {} corrected / {} uncorrected errors ~ {:.1} keys per second",
+            self.state.raw_wpm(),
+            self.state.net_wpm(),
+            self.state.corrected_errors(),
+            self.state.uncorrected_errors(),
+            self.state.keystrokes_per_second(),
+        );
+        lines.push(Line::from(Span::styled(
+            details,
+            self.theme.results.remove_modifier(Modifier::BOLD),
+        )));
+
+        if let Some(rule) = self.state.ended_by {
+            lines.push(Line::from(Span::styled(
+                format!("ended by the {} rule", rule.name()),
+                self.theme.results.remove_modifier(Modifier::BOLD),
+            )));
+        } else if !self.config.rules.is_empty() {
+            let rules: Vec<_> = self.config.rules.iter().map(|rule| rule.name()).collect();
+            let mut summary = format!("completed under {} rules", rules.join(", "));
+            if self.state.restarts > 0 {
+                summary.push_str(&format!(" after {} restarts", self.state.restarts));
+            }
             lines.push(Line::from(Span::styled(
-                results,
-                Style::default()
-                    .fg(Color::Indexed(self.config.fg_results))
-                    .add_modifier(Modifier::BOLD),
+                summary,
+                self.theme.results.remove_modifier(Modifier::BOLD),
             )));
+        }
 
-            lines.push(Line::from(""));
-            lines.push(Line::from("Press Enter to quit, Esc to start new session"));
+        if let Some(seed) = self.state.seed {
+            lines.push(Line::from(Span::styled(
+                format!("seed {seed}"),
+                self.theme.status,
+            )));
         }
 
+        if let Some(ghost) = &self.ghost {
+            let elapsed = self.state.elapsed_seconds();
+            let ghost_elapsed = ghost.duration.as_secs_f64();
+            let verdict = if elapsed <= ghost_elapsed {
+                format!("you were {:.1}s faster", ghost_elapsed - elapsed)
+            } else {
+                format!("you were {:.1}s slower", elapsed - ghost_elapsed)
+            };
+
+            lines.push(Line::from(Span::styled(
+                format!("run {} finished in {:.1}s ~ {}", ghost.id, ghost_elapsed, verdict),
+                self.theme.results.remove_modifier(Modifier::BOLD),
+            )));
+        }
+
+        lines.push(Line::from(""));
+        let stats = key_stats(self.state.keystrokes());
+        lines.extend(render_keyboard(&stats, self.heatmap_view, self.theme));
+
+        lines.push(Line::from(""));
+        lines.push(Line::from(Span::styled(
+            "Press Enter to quit, Esc to start new session, Tab to switch heatmap view",
+            self.theme.hit,
+        )));
+
+        lines
+    }
+
+    pub fn draw(&self, frame: &mut Frame<'_>) {
+        frame.render_widget(Block::default().style(self.theme.background), frame.area());
+
+        let show_status = !self.state.is_complete() && self.config.status_bar != StatusBar::Off;
+
+        let mut header = vec![];
+        if show_status && self.config.status_bar == StatusBar::Top {
+            header.push(self.render_status());
+            header.push(Line::from(""));
+        }
+
+        let mut footer = vec![];
+        if show_status && self.config.status_bar == StatusBar::Bottom {
+            footer.push(Line::from(""));
+            footer.push(self.render_status());
+        }
+        if self.state.is_complete() {
+            footer.extend(self.render_results());
+        }
+
+        // only the rows around the cursor are shown when the text does not fit on screen
+        let mut page = self.render_page();
+        let cursor_row = self.state.cursor_row().min(page.len().saturating_sub(1));
+        let height = (frame.area().height as usize).saturating_sub(header.len() + footer.len());
+        let viewport = Viewport::new(
+            page.len(),
+            cursor_row,
+            height,
+            self.config.lines,
+            self.config.context_lines,
+        );
+
+        let text_offset = (header.len() + cursor_row - viewport.first()) as u16;
+        // drain from the bottom so the remaining ranges stay valid
+        let below: Vec<_> = page.drain(viewport.below.clone()).collect();
+        let window: Vec<_> = page.drain(viewport.window.clone()).collect();
+        let above: Vec<_> = page.drain(viewport.above.clone()).collect();
+
+        let mut lines = header;
+        lines.extend(above.into_iter().map(|line| line.patch_style(Modifier::DIM)));
+        lines.extend(window);
+        lines.extend(below.into_iter().map(|line| line.patch_style(Modifier::DIM)));
+        lines.extend(footer);
+
         let text_height = lines.len() as u16;
         let vertical_margin = frame.area().height.saturating_sub(text_height) / 2;
         let max_line_width = lines
@@ -103,13 +280,66 @@ impl<'a> App<'a> {
         frame.render_widget(paragraph, centered_area);
 
         if !self.state.is_complete() {
-            let (cursor_row, cursor_col) = self.state.cursor();
-            let cursor_x = centered_area.x + cursor_col as u16;
-            let cursor_y = centered_area.y + cursor_row as u16;
+            let cursor_x = centered_area.x + self.state.cursor_col() as u16;
+            let cursor_y = centered_area.y + text_offset;
             frame.set_cursor_position((cursor_x, cursor_y));
         }
     }
 
+    /// Advances time based session state, called on every iteration of the UI loop
+    pub fn tick(&mut self) -> Result<()> {
+        if self.state.is_complete() {
+            return Ok(());
+        }
+
+        if self.state.is_time_up() {
+            self.state.stop_clock();
+        } else if self.state.needs_more_text()
+            && let Some(generator) = &self.generator
+        {
+            let text = generator.generate(&mut self.rng)?;
+            self.state.extend_target(&text);
+        }
+
+        Ok(())
+    }
+
+    fn has_rule(&self, rule: Rule) -> bool {
+        self.config.rules.contains(&rule)
+    }
+
+    fn type_char(&mut self, c: char) {
+        if !self.state.has_started() {
+            self.state.start_clock();
+        }
+
+        self.state.apply_char(c);
+
+        let missed = self.state.keystrokes().last().is_some_and(|k| k.is_miss());
+        if missed && self.has_rule(Rule::SuddenDeath) {
+            self.state.end_by(Rule::SuddenDeath);
+        } else if missed && self.has_rule(Rule::Perfection) {
+            self.state.restart();
+        }
+
+        if self.state.is_complete() {
+            self.state.stop_clock();
+        }
+    }
+
+    pub fn handle_resize(&mut self, columns: u16) {
+        // races, daily challenges and code keep the exact layout of their text
+        let fixed_text = self.ghost.is_some() || self.state.daily.is_some() || self.state.code;
+
+        if self.config.reflow
+            && self.config.width == Width::Auto
+            && !fixed_text
+            && !self.state.is_complete()
+        {
+            self.state.reflow_remaining(Width::for_columns(columns as usize));
+        }
+    }
+
     pub fn handle_key_event(&mut self, key: KeyCode, modifiers: KeyModifiers) {
         if self.state.is_complete() {
             match key {
@@ -121,6 +351,9 @@ impl<'a> App<'a> {
                     self.state.should_loop = true;
                     self.should_quit = true;
                 }
+                KeyCode::Tab => {
+                    self.heatmap_view = self.heatmap_view.toggle();
+                }
                 _ => {
                     // ignore the rest
                 }
@@ -128,6 +361,12 @@ impl<'a> App<'a> {
             return;
         }
 
+        if self.state.is_time_up() {
+            // the time ran out since the last tick, the key must not count
+            self.state.stop_clock();
+            return;
+        }
+
         // Ctrl+key takes precedence
         if modifiers.contains(KeyModifiers::CONTROL) {
             match key {
@@ -135,7 +374,7 @@ impl<'a> App<'a> {
                     self.state.should_loop = false;
                     self.should_quit = true;
                 }
-                KeyCode::Char('w') => {
+                KeyCode::Char('w') if !self.has_rule(Rule::NoBackspace) => {
                     self.state.apply_backspace_word();
                 }
                 _ => {
@@ -147,28 +386,16 @@ impl<'a> App<'a> {
 
         match key {
             KeyCode::Char(c) => {
-                if !self.state.has_started() {
-                    self.state.start_clock();
-                }
-
-                self.state.apply_char(c);
-
-                if self.state.is_complete() {
-                    self.state.stop_clock();
-                }
+                self.type_char(c);
             }
             KeyCode::Enter => {
-                if !self.state.has_started() {
-                    self.state.start_clock();
-                }
-
-                self.state.apply_char('\n');
-
-                if self.state.is_complete() {
-                    self.state.stop_clock();
-                }
+                self.type_char('\n');
             }
-            KeyCode::Backspace => {
+            // Tab fills in indentation, prose has none to fill in
+            KeyCode::Tab if self.state.code => {
+                self.type_char('\t');
+            }
+            KeyCode::Backspace if !self.has_rule(Rule::NoBackspace) => {
                 self.state.apply_backspace();
             }
             KeyCode::Esc => {
@@ -181,3 +408,120 @@ impl<'a> App<'a> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+    use rand::SeedableRng;
+
+    use super::*;
+    use crate::{corpus::Filters, decorate::Decorations, markov::Sampling, text::TextSource};
+
+    fn config(args: &[&str]) -> Config {
+        Config::parse_from(["typegym"].iter().chain(args))
+    }
+
+    fn type_keys(app: &mut App, keys: &str) {
+        for c in keys.chars() {
+            app.handle_key_event(KeyCode::Char(c), KeyModifiers::NONE);
+        }
+    }
+
+    fn app<'a>(config: &'a Config, theme: &'a Theme, text: &str) -> App<'a> {
+        let mut state = State::new(text.to_string());
+        state.time_limit = config.mode.time_limit();
+        App::new(state, config, theme, StdRng::seed_from_u64(0))
+    }
+
+    #[test]
+    fn sudden_death_ends_at_the_first_mistake() {
+        let config = config(&["--rule", "sudden-death"]);
+        let theme = Theme::default();
+        let mut app = app(&config, &theme, "cat");
+
+        type_keys(&mut app, "cx");
+        assert_eq!(app.state.ended_by, Some(Rule::SuddenDeath));
+        assert!(app.state.is_complete());
+
+        type_keys(&mut app, "at");
+        assert_eq!(app.state.input, "cx");
+    }
+
+    #[test]
+    fn perfection_starts_over_after_a_mistake() {
+        let config = config(&["--rule", "perfection"]);
+        let theme = Theme::default();
+        let mut app = app(&config, &theme, "cat");
+
+        type_keys(&mut app, "cax");
+        assert_eq!(app.state.input, "");
+        assert_eq!(app.state.restarts, 1);
+        assert!(!app.state.has_started());
+
+        type_keys(&mut app, "cat");
+        assert!(app.state.is_complete());
+        assert_eq!(app.state.ended_by, None);
+    }
+
+    #[test]
+    fn perfection_keeps_the_clock_running_in_time_mode() {
+        let config = config(&["--rule", "perfection", "--mode", "time:60"]);
+        let theme = Theme::default();
+        let mut app = app(&config, &theme, "cat");
+
+        type_keys(&mut app, "c");
+        let start = app.state.session_start;
+        type_keys(&mut app, "x");
+
+        assert_eq!(app.state.input, "");
+        assert_eq!(app.state.session_start, start);
+    }
+
+    #[test]
+    fn timed_sessions_draw_more_text_from_the_generator() {
+        let config = config(&["--mode", "time:60"]);
+        let theme = Theme::default();
+        let mut app = app(&config, &theme, "cat");
+        app.generator = Some(
+            Generator::new(
+                &TextSource::Static,
+                config.max_words,
+                Decorations::default(),
+                Sampling::default(),
+                &Filters::none(),
+            )
+            .unwrap(),
+        );
+
+        app.tick().unwrap();
+        assert!(app.state.target.starts_with("cat\nThis is a bare minimum example."));
+    }
+
+    #[test]
+    fn tab_only_types_in_code_sessions() {
+        let config = config(&[]);
+        let theme = Theme::default();
+
+        let mut prose = app(&config, &theme, "a b");
+        type_keys(&mut prose, "a");
+        prose.handle_key_event(KeyCode::Tab, KeyModifiers::NONE);
+        assert_eq!(prose.state.input, "a");
+
+        let mut code = app(&config, &theme, "    b");
+        code.state.code = true;
+        code.handle_key_event(KeyCode::Tab, KeyModifiers::NONE);
+        assert_eq!(code.state.input, "    ");
+    }
+
+    #[test]
+    fn no_backspace_keeps_mistakes() {
+        let config = config(&["--rule", "no-backspace"]);
+        let theme = Theme::default();
+        let mut app = app(&config, &theme, "cat");
+
+        type_keys(&mut app, "cx");
+        app.handle_key_event(KeyCode::Backspace, KeyModifiers::NONE);
+        app.handle_key_event(KeyCode::Char('w'), KeyModifiers::CONTROL);
+        assert_eq!(app.state.input, "cx");
+    }
+}
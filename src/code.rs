@@ -0,0 +1,190 @@
+use color_eyre::{Result, eyre::eyre};
+use rand::{Rng, RngExt, seq::IndexedRandom};
+
+/// Snippets longer than this are skipped in favor of shorter definitions
+const MAX_SNIPPET_LINES: usize = 30;
+
+/// Number of lines taken from files without any recognizable definitions
+const FALLBACK_LINES: usize = 15;
+
+/// Tabs are replaced by this many spaces so indentation is typed the same everywhere
+const TAB_WIDTH: usize = 4;
+
+/// Words that may precede a definition keyword, e.g. `pub async fn` or `export default function`
+const QUALIFIERS: &[&str] = &[
+    "pub", "pub(crate)", "pub(super)", "async", "const", "unsafe", "extern", "export", "default",
+];
+
+/// Picks a random function sized snippet from the contents of a source file, keeping its
+/// indentation and newlines
+pub fn pick_snippet(path: &str, contents: &str, rng: &mut impl Rng) -> Result<String> {
+    let lines: Vec<_> = normalize(contents);
+
+    if lines.iter().all(|line| line.is_empty()) {
+        return Err(eyre!("code source {path} contains no code"));
+    }
+
+    let snippets: Vec<_> = (0..lines.len())
+        .filter_map(|start| snippet_at(&lines, start))
+        .filter(|snippet| snippet.len() <= MAX_SNIPPET_LINES)
+        .collect();
+
+    let snippet = match snippets.choose(rng) {
+        Some(snippet) => *snippet,
+        None => {
+            // no definitions found, fall back to a plain excerpt
+            let count = lines.len().min(FALLBACK_LINES);
+            let start = rng.random_range(0..=lines.len() - count);
+            &lines[start..start + count]
+        }
+    };
+
+    Ok(dedent(snippet))
+}
+
+/// Expands tabs and strips trailing whitespace of every line
+fn normalize(contents: &str) -> Vec<String> {
+    contents
+        .lines()
+        .map(|line| line.replace('\t', &" ".repeat(TAB_WIDTH)).trim_end().to_string())
+        .collect()
+}
+
+fn indentation(line: &str) -> usize {
+    line.chars().take_while(|&c| c == ' ').count()
+}
+
+/// Whether the line starts a function in Rust, Python, JavaScript or shell
+fn is_definition(line: &str) -> bool {
+    let mut words = line.split_whitespace().skip_while(|word| QUALIFIERS.contains(word));
+
+    match words.next() {
+        Some("fn" | "def" | "function") => true,
+        // shell style `name() {`
+        Some(word) => word.ends_with("()") && word.len() > 2 && words.next() == Some("{"),
+        None => false,
+    }
+}
+
+/// The lines of the definition starting at `start`, if there is one
+fn snippet_at(lines: &[String], start: usize) -> Option<&[String]> {
+    let first = &lines[start];
+    if !is_definition(first) {
+        return None;
+    }
+
+    let end = if first.ends_with(':') {
+        indented_block_end(lines, start)
+    } else {
+        braced_block_end(lines, start)?
+    };
+
+    Some(&lines[start..end])
+}
+
+/// End of a Python style block, the first line indented no deeper than the definition
+fn indented_block_end(lines: &[String], start: usize) -> usize {
+    let indent = indentation(&lines[start]);
+
+    let mut end = start + 1;
+    for (index, line) in lines.iter().enumerate().skip(start + 1) {
+        if line.is_empty() {
+            continue;
+        }
+        if indentation(line) <= indent {
+            break;
+        }
+        end = index + 1;
+    }
+
+    end
+}
+
+/// End of a brace delimited block, braces in strings and comments are counted too
+fn braced_block_end(lines: &[String], start: usize) -> Option<usize> {
+    let mut depth = 0;
+    let mut opened = false;
+
+    for (index, line) in lines.iter().enumerate().skip(start) {
+        for c in line.chars() {
+            match c {
+                '{' => {
+                    depth += 1;
+                    opened = true;
+                }
+                '}' => depth -= 1,
+                _ => {}
+            }
+        }
+
+        if opened && depth <= 0 {
+            return Some(index + 1);
+        }
+        if !opened && line.ends_with(';') {
+            // declaration without a body
+            return None;
+        }
+    }
+
+    None
+}
+
+/// Removes the indentation shared by all lines and surrounding blank lines
+fn dedent(lines: &[String]) -> String {
+    let indent = lines
+        .iter()
+        .filter(|line| !line.is_empty())
+        .map(|line| indentation(line))
+        .min()
+        .unwrap_or(0);
+
+    let lines: Vec<_> = lines
+        .iter()
+        .map(|line| line.get(indent..).unwrap_or(""))
+        .collect();
+
+    lines.join("\n").trim_matches('\n').to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{SeedableRng, rngs::StdRng};
+
+    use super::*;
+
+    fn lines(text: &str) -> Vec<String> {
+        normalize(text)
+    }
+
+    #[test]
+    fn braced_block_ends_at_the_matching_brace() {
+        let lines = lines("fn a() {\n    if b {\n        c();\n    }\n}\nfn d() {}\nfn e();");
+        assert_eq!(snippet_at(&lines, 0).map(<[_]>::len), Some(5));
+        assert_eq!(snippet_at(&lines, 5).map(<[_]>::len), Some(1));
+        assert_eq!(snippet_at(&lines, 1), None);
+    }
+
+    #[test]
+    fn declaration_without_a_body_is_no_snippet() {
+        let lines = lines("trait A {\n    fn b(&self);\n}");
+        assert_eq!(snippet_at(&lines, 1), None);
+    }
+
+    #[test]
+    fn indented_block_ends_at_the_first_shallower_line() {
+        let lines = lines("def a(x):\n    y = x\n\n    return y\n\nprint(a(1))");
+        assert_eq!(snippet_at(&lines, 0).map(<[_]>::len), Some(4));
+    }
+
+    #[test]
+    fn dedent_removes_shared_indentation_and_surrounding_blank_lines() {
+        let lines = lines("\n    fn a() {\n\n        b\n    }\n");
+        assert_eq!(dedent(&lines), "fn a() {\n\n    b\n}");
+    }
+
+    #[test]
+    fn tabs_are_typed_as_four_spaces() {
+        let snippet = pick_snippet("a.rs", "\tfn a() {\n\t\tb \n\t}", &mut StdRng::seed_from_u64(0));
+        assert_eq!(snippet.unwrap(), "fn a() {\n    b\n}");
+    }
+}
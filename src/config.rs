@@ -1,38 +1,464 @@
-use clap::Parser;
-use crate::text::TextSource;
+use core::{fmt::Display, str::FromStr};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use chrono::NaiveDate;
+use clap::{ArgMatches, Args, CommandFactory, FromArgMatches, Parser, Subcommand, ValueEnum, parser::ValueSource};
+use color_eyre::{Result, eyre::eyre};
+use serde::{Deserialize, Deserializer, de::Error};
+
+use crate::{
+    corpus::Filter,
+    ghost::RaceTarget,
+    markov::{self, Level},
+    mode::Mode,
+    text::{TextSource, Width},
+};
 
 #[derive(Debug, Parser)]
 pub struct Config {
-    /// ANSI color for incorrectly typed character
-    #[arg(long, default_value_t = 1)]
-    pub fg_miss: u8,
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    /// Configuration file to use instead of ~/.config/typegym/config.toml
+    #[arg(long, value_name = "PATH")]
+    pub config: Option<PathBuf>,
 
-    /// ANSI color for untyped character
-    #[arg(long, default_value_t = 8)]
-    pub fg_empty: u8,
+    /// Named theme (default, gruvbox, nord, solarized-light), user theme or path to a theme file
+    #[arg(long, default_value = "default", value_name = "NAME")]
+    pub theme: String,
 
-    /// ANSI color for results line
-    #[arg(long, default_value_t = 1)]
-    pub fg_results: u8,
+    /// ANSI color for incorrectly typed character, overrides the theme
+    #[arg(long)]
+    pub fg_miss: Option<u8>,
+
+    /// ANSI color for untyped character, overrides the theme
+    #[arg(long)]
+    pub fg_empty: Option<u8>,
+
+    /// ANSI color for results line, overrides the theme
+    #[arg(long)]
+    pub fg_results: Option<u8>,
 
     /// The way we get text for our practice sessions
     #[arg(
         long,
         default_value = "markov:data/markov.txt",
         value_name = "SOURCE",
-        help = "Text source: static, nonsense, weighted, file:<path> or markov:<path>"
+        help = "Text source: static, nonsense, weighted, adaptive, file:<path>, markov:<path> or code:<path>"
     )]
     pub text_source: TextSource,
 
+    /// Whether session ends after the text is typed or after a fixed amount of time
+    #[arg(
+        long,
+        default_value = "words",
+        value_name = "MODE",
+        help = "Session mode: words or time:<secs>, e.g. time:15, time:30, time:60 or time:120"
+    )]
+    pub mode: Mode,
+
+    /// Re-run the text of a previous session with a ghost cursor replaying it
+    #[arg(long, value_name = "RUN", help = "Race a previous run: run id from history or best")]
+    pub race: Option<RaceTarget>,
+
+    /// How strictly mistakes are handled while typing
+    #[arg(long, value_enum, default_value_t = ErrorMode::Free)]
+    pub error_mode: ErrorMode,
+
+    /// Session rules, may be given more than once
+    #[arg(long = "rule", value_enum, value_name = "RULE")]
+    pub rules: Vec<Rule>,
+
+    /// Where to show live WPM, accuracy, elapsed time and progress while typing
+    #[arg(long, value_enum, default_value_t = StatusBar::Off)]
+    pub status_bar: StatusBar,
+
+    /// Take today's daily challenge, the same text for everyone on the same UTC date
+    #[arg(long, conflicts_with_all = [
+            "race", "seed", "text_source", "max_words", "mode", "punctuation", "numbers", "capitalize",
+            "rules", "error_mode"
+        ])]
+    pub daily: bool,
+
+    /// Seed for text generation, the same seed and options always produce the same text
+    #[arg(long)]
+    pub seed: Option<u64>,
+
+    /// Number of text lines shown around the cursor, as many as fit on screen when not set
+    #[arg(long, value_name = "N")]
+    pub lines: Option<usize>,
+
+    /// Number of faded lines shown above and below the visible lines
+    #[arg(long, default_value_t = 0, value_name = "N")]
+    pub context_lines: usize,
+
     /// Maximum number of words in generated paragraph
     #[arg(long, default_value_t = 100)]
     pub max_words: usize,
 
-    /// Maximum number of characters per line in reflowed text
-    #[arg(long, default_value_t = 80, requires = "reflow")]
-    pub width: usize,
+    /// Add punctuation to generated words, optionally with the share of words inside a sentence to punctuate
+    #[arg(long, num_args = 0..=1, default_missing_value = "0.2", value_name = "RATE")]
+    pub punctuation: Option<f64>,
+
+    /// Replace generated words with numbers, optionally with the share of words to replace
+    #[arg(long, num_args = 0..=1, default_missing_value = "0.1", value_name = "RATE")]
+    pub numbers: Option<f64>,
+
+    /// Capitalize the first word of every sentence in generated text, sentences are ended by --punctuation
+    #[arg(long, default_value_t = false)]
+    pub capitalize: bool,
+
+    /// Markov sampling temperature, below 1 favors frequent words and above 1 rare ones
+    #[arg(long, default_value_t = 1.0)]
+    pub temperature: f64,
+
+    /// Only pick among this many most frequent words when generating Markov text
+    #[arg(long, value_name = "K")]
+    pub top_k: Option<usize>,
+
+    /// Cleanup step to leave out when reading file and markov corpora, may be given more than once
+    #[arg(long = "skip-filter", value_enum, value_name = "FILTER")]
+    pub skip_filters: Vec<Filter>,
+
+    /// Maximum number of characters per line in reflowed text, or auto to follow the terminal
+    #[arg(long, default_value = "80")]
+    pub width: Width,
 
     /// Reflow text using target width
     #[arg(long, default_value_t = false)]
     pub reflow: bool,
 }
+
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ErrorMode {
+    /// Mistakes are typed like any other character
+    Free,
+
+    /// The cursor does not move until the correct key is pressed
+    Stop,
+
+    /// The cursor does not move past a space while the current word has mistakes
+    Word,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Rule {
+    /// Backspace and Ctrl+W are disabled
+    NoBackspace,
+
+    /// The session ends at the first mistake
+    SuddenDeath,
+
+    /// The same text starts over after any mistake
+    Perfection,
+}
+
+impl Rule {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Rule::NoBackspace => "no backspace",
+            Rule::SuddenDeath => "sudden death",
+            Rule::Perfection => "perfection",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StatusBar {
+    /// Do not show the status bar
+    Off,
+
+    /// Show the status bar above the text
+    Top,
+
+    /// Show the status bar below the text
+    Bottom,
+}
+
+/// Settings read from the configuration file, every one of them is optional
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FileConfig {
+    theme: Option<String>,
+    fg_miss: Option<u8>,
+    fg_empty: Option<u8>,
+    fg_results: Option<u8>,
+    #[serde(default, deserialize_with = "from_str")]
+    text_source: Option<TextSource>,
+    #[serde(default, deserialize_with = "from_str")]
+    mode: Option<Mode>,
+    error_mode: Option<ErrorMode>,
+    rules: Option<Vec<Rule>>,
+    status_bar: Option<StatusBar>,
+    lines: Option<usize>,
+    context_lines: Option<usize>,
+    max_words: Option<usize>,
+    punctuation: Option<f64>,
+    numbers: Option<f64>,
+    capitalize: Option<bool>,
+    temperature: Option<f64>,
+    top_k: Option<usize>,
+    skip_filters: Option<Vec<Filter>>,
+    #[serde(default, deserialize_with = "from_str")]
+    width: Option<Width>,
+    reflow: Option<bool>,
+}
+
+/// Deserializes a string using the same `FromStr` implementation as the command line
+fn from_str<'de, D, T>(deserializer: D) -> core::result::Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: FromStr,
+    T::Err: Display,
+{
+    Option::<String>::deserialize(deserializer)?
+        .map(|value| value.parse().map_err(D::Error::custom))
+        .transpose()
+}
+
+impl FileConfig {
+    fn load(path: &Path) -> Result<Self> {
+        let contents = fs::read_to_string(path)
+            .map_err(|e| eyre!("could not read config file {}: {e}", path.display()))?;
+
+        toml::from_str(&contents)
+            .map_err(|e| eyre!("invalid config file {}:\n{e}", path.display()))
+    }
+}
+
+impl Config {
+    /// Parses command line arguments and layers them over the configuration file
+    pub fn load() -> Result<Self> {
+        let matches = Self::command().get_matches();
+        let config = Self::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+
+        let path = match &config.config {
+            Some(path) => Some(path.clone()),
+            None => default_path().filter(|path| path.exists()),
+        };
+        let file = path.map(|path| FileConfig::load(&path)).transpose()?;
+
+        config.layered(file, &matches)
+    }
+
+    /// Layers the command line over the file, if any, and validates the result
+    fn layered(mut self, file: Option<FileConfig>, matches: &ArgMatches) -> Result<Self> {
+        if let Some(file) = file {
+            self.merge(file, matches);
+        }
+
+        for (name, rate) in [("punctuation", self.punctuation), ("numbers", self.numbers)] {
+            if let Some(rate) = rate
+                && !(0.0..=1.0).contains(&rate)
+            {
+                return Err(eyre!("{name} rate must be between 0 and 1, got {rate}"));
+            }
+        }
+
+        if !(self.temperature.is_finite() && self.temperature > 0.0) {
+            return Err(eyre!("temperature must be a positive number, got {}", self.temperature));
+        }
+        if self.top_k == Some(0) {
+            return Err(eyre!("top-k must be at least 1"));
+        }
+        if self.lines == Some(0) {
+            return Err(eyre!("lines must be at least 1, the cursor line is always shown"));
+        }
+
+        if self.daily {
+            // daily scores compare like with like, the mode, rules and error mode from the file do
+            // not apply
+            self.mode = Mode::Words;
+            self.rules.clear();
+            self.error_mode = ErrorMode::Free;
+        }
+
+        // checked after merging, reflow may be turned on in the file and the width on the command line
+        if matches.value_source("width") == Some(ValueSource::CommandLine) && !self.reflow {
+            return Err(eyre!("--width only applies to reflowed text, pass --reflow as well"));
+        }
+
+        Ok(self)
+    }
+
+    /// Takes values from the file unless they were given on the command line
+    fn merge(&mut self, file: FileConfig, matches: &ArgMatches) {
+        fn layer<T>(matches: &ArgMatches, id: &str, value: &mut T, file_value: Option<T>) {
+            if let Some(file_value) = file_value
+                && matches.value_source(id) != Some(ValueSource::CommandLine)
+            {
+                *value = file_value;
+            }
+        }
+
+        layer(matches, "theme", &mut self.theme, file.theme);
+        layer(matches, "fg_miss", &mut self.fg_miss, file.fg_miss.map(Some));
+        layer(matches, "fg_empty", &mut self.fg_empty, file.fg_empty.map(Some));
+        layer(matches, "fg_results", &mut self.fg_results, file.fg_results.map(Some));
+        layer(matches, "text_source", &mut self.text_source, file.text_source);
+        layer(matches, "mode", &mut self.mode, file.mode);
+        layer(matches, "error_mode", &mut self.error_mode, file.error_mode);
+        layer(matches, "rules", &mut self.rules, file.rules);
+        layer(matches, "status_bar", &mut self.status_bar, file.status_bar);
+        layer(matches, "lines", &mut self.lines, file.lines.map(Some));
+        layer(matches, "context_lines", &mut self.context_lines, file.context_lines);
+        layer(matches, "max_words", &mut self.max_words, file.max_words);
+        layer(matches, "punctuation", &mut self.punctuation, file.punctuation.map(Some));
+        layer(matches, "numbers", &mut self.numbers, file.numbers.map(Some));
+        layer(matches, "capitalize", &mut self.capitalize, file.capitalize);
+        layer(matches, "temperature", &mut self.temperature, file.temperature);
+        layer(matches, "top_k", &mut self.top_k, file.top_k.map(Some));
+        layer(matches, "skip_filters", &mut self.skip_filters, file.skip_filters);
+        layer(matches, "width", &mut self.width, file.width);
+        layer(matches, "reflow", &mut self.reflow, file.reflow);
+    }
+}
+
+fn default_path() -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| dir.join("typegym").join("config.toml"))
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// List, filter and summarize previous practice sessions
+    History(HistoryArgs),
+
+    /// Show the local leaderboard of daily challenges
+    Daily(DailyArgs),
+
+    /// Build a markov model from one or more corpora, for use with markov:<model>.json
+    BuildModel(BuildModelArgs),
+}
+
+#[derive(Debug, Args)]
+pub struct HistoryArgs {
+    /// Only show sessions using this text source, e.g. markov or file:data/words.txt
+    #[arg(long, value_name = "SOURCE")]
+    pub source: Option<String>,
+
+    /// Only show sessions completed on or after this date (YYYY-MM-DD)
+    #[arg(long, value_name = "DATE")]
+    pub since: Option<NaiveDate>,
+
+    /// Only show sessions completed on or before this date (YYYY-MM-DD)
+    #[arg(long, value_name = "DATE")]
+    pub until: Option<NaiveDate>,
+
+    /// Maximum number of sessions to list
+    #[arg(long, default_value_t = 20)]
+    pub limit: usize,
+}
+
+#[derive(Debug, Args)]
+pub struct DailyArgs {
+    /// Show the leaderboard of this date (YYYY-MM-DD) instead of today
+    #[arg(long, value_name = "DATE")]
+    pub date: Option<NaiveDate>,
+
+    /// Number of past days to show best results for
+    #[arg(long, default_value_t = 7)]
+    pub days: usize,
+}
+
+#[derive(Debug, Args)]
+pub struct BuildModelArgs {
+    /// Text files to build the model from
+    #[arg(required = true, value_name = "CORPUS")]
+    pub corpora: Vec<PathBuf>,
+
+    /// Where to save the model, a path ending in .json
+    #[arg(long, short, value_name = "PATH")]
+    pub output: PathBuf,
+
+    /// Number of preceding tokens deciding the next one, 1..=5
+    #[arg(long, default_value_t = markov::DEFAULT_ORDER)]
+    pub order: usize,
+
+    /// Chain words (word), words with their punctuation ending in whole sentences (sentence)
+    /// or characters (char)
+    #[arg(long, default_value = "word")]
+    pub level: Level,
+
+    /// Cleanup step to leave out when reading the corpora, may be given more than once
+    #[arg(long = "skip-filter", value_enum, value_name = "FILTER")]
+    pub skip_filters: Vec<Filter>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Config from fixed command line arguments layered over the contents of a config file
+    fn layered(args: &[&str], file: &str) -> Result<Config> {
+        let matches = Config::command().get_matches_from(["typegym"].iter().chain(args));
+        let config = Config::from_arg_matches(&matches)?;
+        let file: FileConfig = toml::from_str(file)?;
+        config.layered(Some(file), &matches)
+    }
+
+    #[test]
+    fn file_values_replace_defaults() {
+        let config = layered(&[], "theme = \"nord\"\nmode = \"time:60\"\nmax_words = 20").unwrap();
+        assert_eq!(config.theme, "nord");
+        assert_eq!(config.mode, "time:60".parse().unwrap());
+        assert_eq!(config.max_words, 20);
+    }
+
+    #[test]
+    fn command_line_wins_over_the_file() {
+        let config = layered(
+            &["--theme", "gruvbox", "--max-words", "5", "--rule", "perfection"],
+            "theme = \"nord\"\nmax_words = 20\nrules = [\"sudden-death\"]",
+        )
+        .unwrap();
+        assert_eq!(config.theme, "gruvbox");
+        assert_eq!(config.max_words, 5);
+        assert_eq!(config.rules, [Rule::Perfection]);
+    }
+
+    #[test]
+    fn command_line_values_equal_to_the_default_still_win() {
+        let config = layered(
+            &["--theme", "default", "--mode", "words", "--width", "80", "--error-mode", "free"],
+            "theme = \"nord\"\nmode = \"time:60\"\nwidth = \"auto\"\nreflow = true\nerror_mode = \"stop\"",
+        )
+        .unwrap();
+        assert_eq!(config.theme, "default");
+        assert_eq!(config.mode, Mode::Words);
+        assert_eq!(config.width, Width::Fixed(80));
+        assert_eq!(config.error_mode, ErrorMode::Free);
+        assert!(config.reflow);
+    }
+
+    #[test]
+    fn width_needs_reflow_from_either_layer() {
+        assert!(layered(&["--width", "60"], "").is_err());
+        assert!(layered(&["--width", "60"], "reflow = true").is_ok());
+        assert!(layered(&[], "width = \"60\"").is_ok());
+    }
+
+    #[test]
+    fn lines_must_show_the_cursor_line() {
+        assert!(layered(&["--lines", "0"], "").is_err());
+        assert!(layered(&[], "lines = 0").is_err());
+        assert_eq!(layered(&["--lines", "1"], "").unwrap().lines, Some(1));
+    }
+
+    #[test]
+    fn daily_ignores_mode_rules_and_error_mode_of_the_file() {
+        let config = layered(
+            &["--daily"],
+            "mode = \"time:60\"\nrules = [\"perfection\"]\nerror_mode = \"stop\"",
+        )
+        .unwrap();
+        assert_eq!(config.mode, Mode::Words);
+        assert!(config.rules.is_empty());
+        assert_eq!(config.error_mode, ErrorMode::Free);
+    }
+}
@@ -0,0 +1,322 @@
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+use crate::config::Config;
+
+/// Longest line, in words, taken for a chapter title
+const MAX_TITLE_WORDS: usize = 8;
+
+/// Words starting a heading when followed by a number, e.g. `CHAPTER I.` or `Part 2`
+const HEADING_WORDS: &[&str] = &[
+    "chapter", "book", "part", "section", "volume", "act", "scene", "canto", "letter",
+];
+
+/// Cleanup step applied to the files of file and markov sources before they are used
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Filter {
+    /// Remove the byte order mark at the start of the file
+    Bom,
+
+    /// Remove the Project Gutenberg license header and footer
+    Gutenberg,
+
+    /// Drop chapter headings and the titles below them
+    Headings,
+
+    /// Drop lines written in capitals only, e.g. THE END
+    AllCaps,
+
+    /// Replace typographic quotes, dashes, ellipses and spaces with ASCII
+    Punctuation,
+
+    /// Collapse runs of spaces and blank lines
+    Whitespace,
+}
+
+impl Filter {
+    /// Every filter, in the order they are applied
+    pub const ALL: [Filter; 6] = [
+        Filter::Bom,
+        Filter::Gutenberg,
+        Filter::Headings,
+        Filter::AllCaps,
+        Filter::Punctuation,
+        Filter::Whitespace,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Filter::Bom => "bom",
+            Filter::Gutenberg => "gutenberg",
+            Filter::Headings => "headings",
+            Filter::AllCaps => "all-caps",
+            Filter::Punctuation => "punctuation",
+            Filter::Whitespace => "whitespace",
+        }
+    }
+}
+
+/// The filters a corpus goes through, all of them unless some are skipped
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Filters {
+    pub enabled: Vec<Filter>,
+}
+
+impl Default for Filters {
+    fn default() -> Self {
+        Self::without(&[])
+    }
+}
+
+impl Filters {
+    pub fn from_config(config: &Config) -> Self {
+        Self::without(&config.skip_filters)
+    }
+
+    /// Every filter except the skipped ones
+    pub fn without(skipped: &[Filter]) -> Self {
+        Self {
+            enabled: Filter::ALL
+                .into_iter()
+                .filter(|filter| !skipped.contains(filter))
+                .collect(),
+        }
+    }
+
+    /// No filters at all, the text is used as it is
+    pub fn none() -> Self {
+        Self { enabled: Vec::new() }
+    }
+
+    fn has(&self, filter: Filter) -> bool {
+        self.enabled.contains(&filter)
+    }
+
+    /// Names of the enabled filters, distinguishes corpora cleaned up differently
+    pub fn key(&self) -> String {
+        let names: Vec<_> = self.enabled.iter().map(Filter::name).collect();
+        names.join(",")
+    }
+
+    /// Runs the text through every enabled filter
+    pub fn apply(&self, text: &str) -> String {
+        let mut text = text;
+        if self.has(Filter::Bom) {
+            text = text.strip_prefix('\u{feff}').unwrap_or(text);
+        }
+        if self.has(Filter::Gutenberg) {
+            text = strip_gutenberg(text);
+        }
+
+        let mut lines: Vec<_> = text.lines().collect();
+        if self.has(Filter::Headings) {
+            lines = drop_headings(&lines);
+        }
+        if self.has(Filter::AllCaps) {
+            lines.retain(|line| !is_all_caps(line));
+        }
+
+        let mut lines: Vec<_> = lines.into_iter().map(String::from).collect();
+        if self.has(Filter::Punctuation) {
+            lines = lines.iter().map(|line| normalize_punctuation(line)).collect();
+        }
+        if self.has(Filter::Whitespace) {
+            lines = collapse_whitespace(&lines);
+        }
+
+        lines.join("\n")
+    }
+}
+
+/// The text between the `*** START OF` and `*** END OF` markers of a Project Gutenberg book,
+/// the whole text when it has no such markers
+fn strip_gutenberg(text: &str) -> &str {
+    let is_marker = |line: &str, kind: &str| {
+        let line = line.trim_start().to_uppercase();
+        line.starts_with("***") && line.contains(kind) && line.contains("PROJECT GUTENBERG")
+    };
+
+    let mut start = 0;
+    let mut end = text.len();
+    let mut offset = 0;
+
+    for line in text.split_inclusive('\n') {
+        if is_marker(line, "START OF") {
+            start = offset + line.len();
+        } else if is_marker(line, "END OF") && offset >= start {
+            end = offset;
+            break;
+        }
+        offset += line.len();
+    }
+
+    &text[start..end]
+}
+
+/// Lines without headings like `CHAPTER I.` and the title line directly following them. Headings
+/// stand apart with blank lines around them, so short lines of verse or dialogue are kept
+fn drop_headings<'a>(lines: &[&'a str]) -> Vec<&'a str> {
+    let is_blank = |index: usize| lines.get(index).is_none_or(|line| line.trim().is_empty());
+    let mut headings = vec![false; lines.len()];
+
+    for (index, line) in lines.iter().enumerate() {
+        let blank_before = index == 0 || is_blank(index - 1);
+        if !blank_before || !is_numbered_heading(line.trim()) {
+            continue;
+        }
+
+        if is_blank(index + 1) {
+            headings[index] = true;
+        } else if is_blank(index + 2)
+            && lines[index + 1].split_whitespace().count() <= MAX_TITLE_WORDS
+        {
+            // a title below the heading, e.g. `Down the Rabbit-Hole` below `CHAPTER I.`
+            headings[index] = true;
+            headings[index + 1] = true;
+        }
+    }
+
+    lines
+        .iter()
+        .zip(headings)
+        .filter(|(_, heading)| !heading)
+        .map(|(line, _)| *line)
+        .collect()
+}
+
+/// Whether the line is a heading word followed by a number, e.g. `CHAPTER XII.` or `Book 3: Home`
+fn is_numbered_heading(line: &str) -> bool {
+    let mut words = line.split_whitespace();
+    let (Some(word), Some(number)) = (words.next(), words.next()) else {
+        return false;
+    };
+
+    let number = number.trim_end_matches(|c: char| c.is_ascii_punctuation());
+    let is_number = !number.is_empty()
+        && (number.chars().all(|c| c.is_ascii_digit())
+            || number.chars().all(|c| "IVXLCDMivxlcdm".contains(c)));
+
+    // longer lines are sentences starting like a heading, e.g. `Part 2 of the plan was`
+    let short = line.split_whitespace().count() <= MAX_TITLE_WORDS;
+
+    HEADING_WORDS.contains(&word.to_lowercase().as_str()) && is_number && short
+}
+
+/// Whether the line has letters and all of them are capitals, a single `I` does not count
+fn is_all_caps(line: &str) -> bool {
+    let letters: Vec<_> = line.chars().filter(|c| c.is_alphabetic()).collect();
+    letters.len() > 1 && letters.iter().all(|c| c.is_uppercase())
+}
+
+/// Replaces typographic quotes, dashes, ellipses and spaces with their ASCII counterparts
+pub fn normalize_punctuation(string: &str) -> String {
+    let mut normalized = String::with_capacity(string.len());
+
+    for c in string.chars() {
+        match c {
+            '“' | '”' | '„' | '‟' | '«' | '»' | '″' => normalized.push('"'),
+            '‘' | '’' | '‚' | '‛' | '‹' | '›' | '′' => normalized.push('\''),
+            '—' | '–' | '‒' | '―' | '‐' | '‑' | '−' => normalized.push('-'),
+            '…' => normalized.push_str("..."),
+            '\u{a0}' | '\u{2007}' | '\u{2009}' | '\u{200a}' | '\u{202f}' => normalized.push(' '),
+            // zero width spaces and stray byte order marks
+            '\u{200b}' | '\u{feff}' => {}
+            c => normalized.push(c),
+        }
+    }
+
+    normalized
+}
+
+/// Trims lines, collapses runs of spaces into one and runs of blank lines into a single blank line
+fn collapse_whitespace(lines: &[String]) -> Vec<String> {
+    let mut collapsed: Vec<String> = Vec::new();
+
+    for line in lines {
+        let line = line.split_whitespace().collect::<Vec<_>>().join(" ");
+        let previous_blank = collapsed.last().is_none_or(|previous| previous.is_empty());
+        if !line.is_empty() || !previous_blank {
+            collapsed.push(line);
+        }
+    }
+
+    if collapsed.last().is_some_and(|line| line.is_empty()) {
+        collapsed.pop();
+    }
+
+    collapsed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn only(filter: Filter, text: &str) -> String {
+        Filters { enabled: vec![filter] }.apply(text)
+    }
+
+    #[test]
+    fn strips_the_byte_order_mark() {
+        assert_eq!(only(Filter::Bom, "\u{feff}Alice"), "Alice");
+    }
+
+    #[test]
+    fn keeps_the_text_between_gutenberg_markers() {
+        let text = "License\n*** START OF THE PROJECT GUTENBERG EBOOK ALICE ***\nAlice\n\
+            *** END OF THE PROJECT GUTENBERG EBOOK ALICE ***\nMore license";
+        assert_eq!(only(Filter::Gutenberg, text), "Alice");
+        assert_eq!(only(Filter::Gutenberg, "Alice\nRabbit"), "Alice\nRabbit");
+    }
+
+    #[test]
+    fn drops_chapter_headings_and_their_titles() {
+        let text = "CHAPTER I.\nDown the Rabbit-Hole\n\nAlice was tired.\n\nChapter 2\n\nThe end.";
+        assert_eq!(only(Filter::Headings, text), "\nAlice was tired.\n\n\nThe end.");
+    }
+
+    #[test]
+    fn keeps_short_lines_of_verse_and_dialogue() {
+        let text = "How doth the little crocodile\nImprove his shining tail\n\nYes indeed\n\n\
+            Part 2 of the plan was to run away before the Queen noticed";
+        assert_eq!(only(Filter::Headings, text), text);
+    }
+
+    #[test]
+    fn drops_all_caps_lines() {
+        assert_eq!(only(Filter::AllCaps, "Alice\nTHE END\nI\nCHORUS."), "Alice\nI");
+    }
+
+    #[test]
+    fn replaces_typographic_punctuation() {
+        assert_eq!(
+            only(Filter::Punctuation, "“Well…” she said – «oui»\u{a0}‚fine’"),
+            "\"Well...\" she said - \"oui\" 'fine'"
+        );
+    }
+
+    #[test]
+    fn collapses_whitespace() {
+        assert_eq!(
+            only(Filter::Whitespace, "\n  Alice   was\n\n\n\ntired  \n\n"),
+            "Alice was\n\ntired"
+        );
+    }
+
+    #[test]
+    fn no_filters_keep_the_text() {
+        let text = "CHAPTER I.\n“Alice”  was\n\n\nTHE END";
+        assert_eq!(Filters::none().apply(text), text);
+    }
+
+    #[test]
+    fn cleans_up_the_bundled_corpus() {
+        let text = Filters::default().apply(&std::fs::read_to_string("data/markov.txt").unwrap());
+
+        assert!(text.starts_with("Alice was beginning to get very tired"));
+        assert!(!text.contains("CHAPTER") && !text.contains("Rabbit-Hole\n"));
+        assert!(!text.contains("\n\n\n") && !text.contains('“'));
+        assert!(text.ends_with("the happy summer\ndays."));
+    }
+}
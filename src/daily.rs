@@ -0,0 +1,100 @@
+use std::cmp::Reverse;
+
+use chrono::{Local, NaiveDate, Utc};
+use color_eyre::Result;
+use rand::Rng;
+
+use crate::{
+    config::DailyArgs,
+    corpus::Filters,
+    decorate::Decorations,
+    history::{History, Record, text_hash},
+    markov::Sampling,
+    text::{TextSource, generate_text},
+};
+
+/// Every daily challenge uses the same source so the text only depends on the date
+pub const SOURCE: TextSource = TextSource::GenerateWeightedNonsense;
+
+/// Number of words in a daily challenge
+const MAX_WORDS: usize = 50;
+
+/// Current date in UTC, so the whole team switches to a new challenge at the same moment
+pub fn today() -> NaiveDate {
+    Utc::now().date_naive()
+}
+
+/// Seed derived from the date, identical for everyone on that day
+pub fn seed(date: NaiveDate) -> u64 {
+    text_hash(&format!("typegym-daily-{date}"))
+}
+
+pub fn get_text(rng: &mut impl Rng) -> Result<String> {
+    generate_text(
+        &SOURCE,
+        MAX_WORDS,
+        &Decorations::default(),
+        &Sampling::default(),
+        &Filters::default(),
+        rng,
+    )
+}
+
+/// Prints attempts of a daily challenge ranked by words per minute
+pub fn show(args: &DailyArgs) -> Result<()> {
+    let history = History::load()?;
+    let date = args.date.unwrap_or_else(today);
+
+    let mut attempts: Vec<&Record> = history
+        .records
+        .iter()
+        .filter(|r| r.daily == Some(date) && r.ended_by.is_none())
+        .collect();
+    attempts.sort_by(|a, b| b.wpm.total_cmp(&a.wpm));
+
+    println!("Daily challenge {date}");
+    println!();
+
+    if attempts.is_empty() {
+        println!("No attempts yet, run typegym --daily to take part");
+    } else {
+        println!(
+            "{:>4}  {:>5}  {:<5}  {:>5}  {:>5}  {:>7}",
+            "RANK", "ID", "TIME", "WPM", "ACC", "TOOK"
+        );
+        for (rank, record) in attempts.iter().enumerate() {
+            println!(
+                "{:>4}  {:>5}  {:<5}  {:>5.0}  {:>4.0}%  {:>6.1}s",
+                rank + 1,
+                record.id,
+                record.timestamp.with_timezone(&Local).format("%H:%M"),
+                record.wpm,
+                record.accuracy * 100.0,
+                record.duration,
+            );
+        }
+    }
+
+    let mut days: Vec<(NaiveDate, f64)> = Vec::new();
+    for record in history.records.iter().filter(|r| r.ended_by.is_none()) {
+        let Some(day) = record.daily else {
+            continue;
+        };
+
+        match days.iter_mut().find(|(d, _)| *d == day) {
+            Some((_, best)) => *best = best.max(record.wpm),
+            None => days.push((day, record.wpm)),
+        }
+    }
+    days.sort_by_key(|(day, _)| Reverse(*day));
+
+    if !days.is_empty() {
+        println!();
+        println!("{:<10}  {:>5}", "DAY", "BEST");
+        for (day, best) in days.iter().take(args.days) {
+            println!("{:<10}  {:>5.0}", day.to_string(), best);
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,137 @@
+use rand::{Rng, RngExt, seq::IndexedRandom};
+
+use crate::config::Config;
+
+/// Chance of a word ending its sentence when punctuation is added
+const SENTENCE_END_RATE: f64 = 0.12;
+
+/// Punctuation ending a sentence, with weights
+const SENTENCE_ENDS: [(&str, u32); 3] = [(".", 6), ("?", 1), ("!", 1)];
+
+/// Punctuation inside a sentence as text before and after the word, with weights
+const INNER_PUNCTUATION: [(&str, &str, u32); 6] = [
+    ("", ",", 6),
+    ("", ";", 1),
+    ("", ":", 1),
+    ("\"", "\"", 2),
+    ("'", "'", 1),
+    ("(", ")", 2),
+];
+
+/// Punctuation, numbers and capitalization mixed into generated words
+#[derive(Debug, Clone, Default)]
+pub struct Decorations {
+    /// Share of words inside a sentence followed by a comma or wrapped in quotes or parentheses
+    pub punctuation: f64,
+
+    /// Share of words replaced by a number
+    pub numbers: f64,
+
+    /// Capitalize the first word of every sentence, only the first word of the text when there is
+    /// no punctuation to end sentences
+    pub capitalize: bool,
+}
+
+impl Decorations {
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            punctuation: config.punctuation.unwrap_or(0.0),
+            numbers: config.numbers.unwrap_or(0.0),
+            capitalize: config.capitalize,
+        }
+    }
+}
+
+/// Applies decorations to the words, leaving them untouched (and the RNG unused) when there are none
+pub fn decorate(words: &[&str], decorations: &Decorations, rng: &mut impl Rng) -> Vec<String> {
+    let mut decorated = Vec::with_capacity(words.len());
+    let mut sentence_start = true;
+
+    for (index, word) in words.iter().enumerate() {
+        let mut word = if decorations.numbers > 0.0 && rng.random_bool(decorations.numbers) {
+            number(rng)
+        } else {
+            word.to_string()
+        };
+
+        if decorations.capitalize && sentence_start {
+            word = capitalize(&word);
+        }
+
+        // sentences only end where punctuation shows it, otherwise the text is a single sentence
+        let last = index + 1 == words.len();
+        let sentence_end =
+            decorations.punctuation > 0.0 && (last || rng.random_bool(SENTENCE_END_RATE));
+
+        if decorations.punctuation > 0.0 {
+            if sentence_end {
+                let (end, _) = SENTENCE_ENDS.choose_weighted(rng, |item| item.1).unwrap();
+                word.push_str(end);
+            } else if rng.random_bool(decorations.punctuation) {
+                let (before, after, _) = INNER_PUNCTUATION
+                    .choose_weighted(rng, |item| item.2)
+                    .unwrap();
+                word = format!("{before}{word}{after}");
+            }
+        }
+
+        sentence_start = sentence_end;
+        decorated.push(word);
+    }
+
+    decorated
+}
+
+/// A number of one to four digits
+fn number(rng: &mut impl Rng) -> String {
+    let digits = rng.random_range(1..=4);
+    rng.random_range(0..10u32.pow(digits)).to_string()
+}
+
+fn capitalize(word: &str) -> String {
+    let mut chars = word.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{SeedableRng, rngs::StdRng};
+
+    use super::*;
+
+    const WORDS: [&str; 40] = ["word"; 40];
+
+    fn decorated(decorations: &Decorations) -> Vec<String> {
+        decorate(&WORDS, decorations, &mut StdRng::seed_from_u64(1))
+    }
+
+    #[test]
+    fn no_decorations_keep_the_words() {
+        assert_eq!(decorated(&Decorations::default()), WORDS);
+    }
+
+    #[test]
+    fn capitalize_alone_only_capitalizes_the_first_word() {
+        let words = decorated(&Decorations { capitalize: true, ..Decorations::default() });
+        assert_eq!(words[0], "Word");
+        assert!(words[1..].iter().all(|word| word == "word"));
+    }
+
+    #[test]
+    fn capitalized_words_follow_sentence_ends() {
+        let words = decorated(&Decorations {
+            punctuation: 0.2,
+            capitalize: true,
+            ..Decorations::default()
+        });
+
+        assert!(words.last().unwrap().ends_with(['.', '?', '!']));
+        for pair in words.windows(2) {
+            let capitalized = pair[1].trim_start_matches(['"', '\'', '(']).starts_with('W');
+            assert_eq!(capitalized, pair[0].ends_with(['.', '?', '!']), "{pair:?}");
+        }
+    }
+}
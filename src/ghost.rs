@@ -0,0 +1,154 @@
+use core::{fmt, str::FromStr};
+use std::time::Duration;
+
+use color_eyre::{Result, eyre::eyre};
+
+use crate::{
+    history::{History, Replay},
+    keystroke::Keystroke,
+    mode::Mode,
+    state::State,
+};
+
+/// Which previous run to race against
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RaceTarget {
+    /// Session with the given history id
+    Run(u64),
+
+    /// The fastest completed session in words mode
+    Best,
+}
+
+impl FromStr for RaceTarget {
+    type Err = String;
+
+    fn from_str(target: &str) -> core::result::Result<Self, Self::Err> {
+        if target == "best" {
+            return Ok(RaceTarget::Best);
+        }
+
+        target
+            .parse::<u64>()
+            .map(RaceTarget::Run)
+            .map_err(|_| "expected run id from history or best".to_string())
+    }
+}
+
+impl fmt::Display for RaceTarget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RaceTarget::Run(id) => write!(f, "{id}"),
+            RaceTarget::Best => write!(f, "best"),
+        }
+    }
+}
+
+/// A previous run replayed alongside the current session
+#[derive(Debug)]
+pub struct Ghost {
+    /// History id of the replayed run
+    pub id: u64,
+
+    /// Text source of the replayed run
+    pub source: String,
+
+    /// The text typed in the replayed run
+    pub target: String,
+
+    /// Whether the replayed run was code practice
+    pub code: bool,
+
+    /// Ghost cursor position (in characters) after each keystroke
+    timeline: Vec<(Duration, usize)>,
+
+    /// The time the replayed run took to complete
+    pub duration: Duration,
+}
+
+impl Ghost {
+    pub fn load(race: RaceTarget) -> Result<Self> {
+        let history = History::load()?;
+        let words = Mode::Words.to_string();
+
+        let record = match race {
+            RaceTarget::Run(id) => {
+                let record = history
+                    .records
+                    .iter()
+                    .find(|r| r.id == id)
+                    .ok_or_else(|| eyre!("there is no run {id} in history"))?;
+                if record.mode != words {
+                    return Err(eyre!(
+                        "run {id} was a {} session, only words sessions can be raced",
+                        record.mode
+                    ));
+                }
+                if let Some(rule) = &record.ended_by {
+                    return Err(eyre!(
+                        "run {id} was ended early by the {rule} rule, only completed runs can be raced"
+                    ));
+                }
+                record
+            }
+            RaceTarget::Best => history
+                .records
+                .iter()
+                .filter(|r| r.mode == words && r.ended_by.is_none() && Replay::exists(r.id))
+                .max_by(|a, b| a.wpm.total_cmp(&b.wpm))
+                .ok_or_else(|| eyre!("there are no recorded runs to race against yet"))?,
+        };
+
+        let replay = Replay::load(record.id)?;
+        Ok(Self::new(
+            record.id,
+            record.source.clone(),
+            replay.target,
+            replay.code,
+            &replay.keystrokes,
+        ))
+    }
+
+    fn new(id: u64, source: String, target: String, code: bool, keystrokes: &[Keystroke]) -> Self {
+        // replay keystrokes on a blank session to find out where the cursor was at each moment
+        let mut state = State::new(target.clone());
+        state.code = code;
+        let mut timeline = vec![(Duration::ZERO, 0)];
+
+        for keystroke in keystrokes {
+            state.replay(keystroke);
+            timeline.push((keystroke.at, state.input.chars().count()));
+        }
+
+        let duration = keystrokes.last().map_or(Duration::ZERO, |k| k.at);
+
+        Self {
+            id,
+            source,
+            target,
+            code,
+            timeline,
+            duration,
+        }
+    }
+
+    /// Ghost cursor position (in characters) the given time into the session
+    pub fn position(&self, elapsed: Duration) -> usize {
+        let index = self.timeline.partition_point(|(at, _)| *at <= elapsed);
+        self.timeline[index.saturating_sub(1)].1
+    }
+
+    /// Ghost cursor position as `(row, col)` in the target text
+    pub fn cursor(&self, elapsed: Duration) -> (usize, usize) {
+        let position = self.position(elapsed);
+        let typed = self.target.chars().take(position);
+
+        typed.fold((0, 0), |(row, col), c| {
+            if c == '\n' {
+                (row + 1, 0)
+            } else {
+                (row, col + 1)
+            }
+        })
+    }
+}
@@ -0,0 +1,152 @@
+use std::{collections::BTreeMap, time::Duration};
+
+use ratatui::{
+    style::Style,
+    text::{Line, Span},
+};
+
+use crate::{stats::KeyStats, theme::Theme};
+
+const ROWS: [(&str, usize); 4] = [
+    ("`1234567890-=", 0),
+    ("qwertyuiop[]\\", 2),
+    ("asdfghjkl;'", 3),
+    ("zxcvbnm,./", 5),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HeatmapView {
+    /// Color keys by miss rate
+    Errors,
+
+    /// Color keys by average latency
+    Speed,
+}
+
+impl HeatmapView {
+    pub fn toggle(self) -> Self {
+        match self {
+            HeatmapView::Errors => HeatmapView::Speed,
+            HeatmapView::Speed => HeatmapView::Errors,
+        }
+    }
+
+    fn legend(&self) -> &'static str {
+        match self {
+            HeatmapView::Errors => "errors: green no misses ~ yellow under 5% ~ red 5% or more",
+            HeatmapView::Speed => {
+                "speed: green faster ~ yellow about average ~ red slower than your average"
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Heat {
+    Cold,
+    Warm,
+    Hot,
+}
+
+impl Heat {
+    fn style(&self, theme: &Theme) -> Style {
+        match self {
+            Heat::Cold => theme.heat_cold,
+            Heat::Warm => theme.heat_warm,
+            Heat::Hot => theme.heat_hot,
+        }
+    }
+}
+
+/// Whether the stats tell anything about the key in this view, a key that was only typed first
+/// or blocked by the error mode has no latency
+fn has_data(stats: &KeyStats, view: HeatmapView) -> bool {
+    match view {
+        HeatmapView::Errors => stats.presses > 0,
+        HeatmapView::Speed => stats.latency_samples > 0,
+    }
+}
+
+fn heat(stats: &KeyStats, view: HeatmapView, average_latency: Duration) -> Heat {
+    match view {
+        HeatmapView::Errors => match stats.miss_rate() {
+            0.0 => Heat::Cold,
+            rate if rate < 0.05 => Heat::Warm,
+            _ => Heat::Hot,
+        },
+        HeatmapView::Speed => {
+            let ratio = stats.average_latency().as_secs_f64()
+                / average_latency.as_secs_f64().max(f64::EPSILON);
+            if ratio < 0.8 {
+                Heat::Cold
+            } else if ratio <= 1.2 {
+                Heat::Warm
+            } else {
+                Heat::Hot
+            }
+        }
+    }
+}
+
+fn render_key(
+    label: String,
+    stats: Option<&KeyStats>,
+    view: HeatmapView,
+    average_latency: Duration,
+    theme: &Theme,
+) -> Span<'static> {
+    match stats {
+        Some(stats) if has_data(stats, view) => {
+            Span::styled(label, heat(stats, view, average_latency).style(theme))
+        }
+        _ => Span::styled(label, theme.empty),
+    }
+}
+
+/// Renders an on-screen QWERTY keyboard colored by per key stats
+pub fn render_keyboard(
+    stats: &BTreeMap<char, KeyStats>,
+    view: HeatmapView,
+    theme: &Theme,
+) -> Vec<Line<'static>> {
+    let mut total = KeyStats::default();
+    for s in stats.values() {
+        total += *s;
+    }
+    let average_latency = total.average_latency();
+
+    let mut lines = Vec::new();
+    for (keys, indent) in ROWS {
+        let mut spans = vec![Span::raw(" ".repeat(indent))];
+        for key in keys.chars() {
+            spans.push(render_key(
+                format!(" {key} "),
+                stats.get(&key),
+                view,
+                average_latency,
+                theme,
+            ));
+            spans.push(Span::raw(" "));
+        }
+        lines.push(Line::from(spans));
+    }
+
+    lines.push(Line::from(vec![
+        Span::raw(" ".repeat(16)),
+        render_key(
+            format!("{:^26}", "space"),
+            stats.get(&' '),
+            view,
+            average_latency,
+            theme,
+        ),
+    ]));
+
+    lines.push(Line::from(""));
+    lines.push(Line::from(Span::styled(
+        view.legend(),
+        theme.status,
+    )));
+
+    lines
+}
@@ -0,0 +1,369 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use chrono::{DateTime, Datelike, Local, NaiveDate, Utc};
+use color_eyre::{Result, eyre::eyre};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::{Config, HistoryArgs},
+    daily,
+    keystroke::Keystroke,
+    mode::Mode,
+    state::State,
+    stats::{KeyStats, bigram_stats, key_stats},
+};
+
+/// Version of the on-disk history format, bumped on incompatible changes
+const VERSION: u32 = 1;
+
+/// A single completed practice session
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Record {
+    /// Sequential identifier of the session
+    pub id: u64,
+
+    /// The time at which the session was completed
+    pub timestamp: DateTime<Utc>,
+
+    /// Text source used to generate the target text
+    pub source: String,
+
+    /// Session mode, e.g. words or time:60
+    #[serde(default = "default_mode")]
+    pub mode: String,
+
+    /// Number of words typed
+    pub words: usize,
+
+    /// Words per minute
+    pub wpm: f64,
+
+    /// Accuracy in range 0.0..=1.0
+    pub accuracy: f64,
+
+    /// Session duration in seconds
+    pub duration: f64,
+
+    /// Hash of the target text, identifies sessions with the same text
+    pub text_hash: u64,
+
+    /// Seed the target text was generated with
+    #[serde(default)]
+    pub seed: Option<u64>,
+
+    /// Name of the session rule which ended the session early
+    #[serde(default)]
+    pub ended_by: Option<String>,
+
+    /// Date of the daily challenge, daily sessions are kept out of the regular history listing
+    #[serde(default)]
+    pub daily: Option<NaiveDate>,
+
+    /// Miss counts and latencies per physical key
+    #[serde(default)]
+    pub keys: BTreeMap<char, KeyStats>,
+
+    /// Miss counts and latencies per pair of consecutively typed characters
+    #[serde(default)]
+    pub bigrams: BTreeMap<String, KeyStats>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct History {
+    pub version: u32,
+    pub records: Vec<Record>,
+}
+
+impl Default for History {
+    fn default() -> Self {
+        Self {
+            version: VERSION,
+            records: Vec::new(),
+        }
+    }
+}
+
+/// Full keystroke timing of a completed session, stored next to the history
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Replay {
+    pub version: u32,
+    pub target: String,
+
+    /// Whether the run was code practice, which handles whitespace keys differently
+    #[serde(default)]
+    pub code: bool,
+
+    pub keystrokes: Vec<Keystroke>,
+}
+
+fn data_dir() -> Result<PathBuf> {
+    let data_dir = dirs::data_dir().ok_or_else(|| eyre!("could not determine data directory"))?;
+    Ok(data_dir.join("typegym"))
+}
+
+/// Writes into a temporary file first so a crash never leaves a truncated file behind
+pub fn write_atomically(path: &Path, contents: String) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let tmp_path = path.with_extension("json.tmp");
+    fs::write(&tmp_path, contents)?;
+    fs::rename(&tmp_path, path)?;
+
+    Ok(())
+}
+
+impl Replay {
+    fn path(id: u64) -> Result<PathBuf> {
+        Ok(data_dir()?.join("runs").join(format!("{id}.json")))
+    }
+
+    pub fn exists(id: u64) -> bool {
+        Self::path(id).is_ok_and(|path| path.exists())
+    }
+
+    pub fn load(id: u64) -> Result<Self> {
+        let path = Self::path(id)?;
+        let contents = fs::read_to_string(&path).map_err(|e| {
+            eyre!(
+                "could not read replay of run {id} from {}: {e}",
+                path.display()
+            )
+        })?;
+        let replay: Self = serde_json::from_str(&contents)
+            .map_err(|e| eyre!("could not parse replay file {}: {e}", path.display()))?;
+
+        if replay.version > VERSION {
+            return Err(eyre!(
+                "replay file {} has version {}, but only version {VERSION} is supported",
+                path.display(),
+                replay.version
+            ));
+        }
+
+        Ok(replay)
+    }
+
+    pub fn save(&self, id: u64) -> Result<()> {
+        write_atomically(&Self::path(id)?, serde_json::to_string(self)?)
+    }
+}
+
+impl History {
+    pub fn path() -> Result<PathBuf> {
+        Ok(data_dir()?.join("history.json"))
+    }
+
+    pub fn load() -> Result<Self> {
+        let path = Self::path()?;
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+
+        let contents = fs::read_to_string(&path)?;
+        let history: Self = serde_json::from_str(&contents)
+            .map_err(|e| eyre!("could not parse history file {}: {e}", path.display()))?;
+
+        if history.version > VERSION {
+            return Err(eyre!(
+                "history file {} has version {}, but only version {VERSION} is supported",
+                path.display(),
+                history.version
+            ));
+        }
+
+        Ok(history)
+    }
+
+    pub fn save(&self) -> Result<()> {
+        write_atomically(&Self::path()?, serde_json::to_string_pretty(self)?)
+    }
+
+    pub fn append(&mut self, mut record: Record) -> u64 {
+        record.id = self
+            .records
+            .iter()
+            .map(|r| r.id)
+            .max()
+            .map_or(1, |id| id + 1);
+        let id = record.id;
+        self.records.push(record);
+        id
+    }
+}
+
+impl Record {
+    pub fn new(state: &State, config: &Config) -> Self {
+        Self {
+            id: 0,
+            timestamp: Utc::now(),
+            source: match (&state.daily, &state.race_source) {
+                (Some(_), _) => daily::SOURCE.to_string(),
+                (None, Some(source)) => source.clone(),
+                (None, None) => config.text_source.to_string(),
+            },
+            // races only replay words sessions, whatever mode is configured
+            mode: match state.race_source {
+                Some(_) => Mode::Words.to_string(),
+                None => config.mode.to_string(),
+            },
+            words: state.input.split_whitespace().count(),
+            wpm: state.wpm(),
+            accuracy: state.accuracy(),
+            duration: state.elapsed_seconds(),
+            text_hash: text_hash(&state.target),
+            seed: state.seed,
+            ended_by: state.ended_by.map(|rule| rule.name().to_string()),
+            daily: state.daily,
+            keys: key_stats(state.keystrokes()),
+            bigrams: bigram_stats(state.keystrokes()),
+        }
+    }
+
+    fn local_date(&self) -> NaiveDate {
+        self.timestamp.with_timezone(&Local).date_naive()
+    }
+
+    fn matches_source(&self, source: &str) -> bool {
+        self.source == source || self.source.split(':').next() == Some(source)
+    }
+}
+
+fn default_mode() -> String {
+    "words".to_string()
+}
+
+/// Appends a completed session to the history file and stores its keystrokes for later races
+pub fn record_session(state: &State, config: &Config) -> Result<u64> {
+    let mut history = History::load()?;
+    let id = history.append(Record::new(state, config));
+
+    let replay = Replay {
+        version: VERSION,
+        target: state.target.clone(),
+        code: state.code,
+        keystrokes: state.keystrokes().cloned().collect(),
+    };
+    replay.save(id)?;
+    history.save()?;
+
+    Ok(id)
+}
+
+/// FNV-1a hash, stable across Rust versions unlike `DefaultHasher`
+pub fn text_hash(text: &str) -> u64 {
+    text.bytes().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+pub fn show(args: &HistoryArgs) -> Result<()> {
+    let history = History::load()?;
+
+    let records: Vec<_> = history
+        .records
+        .iter()
+        .filter(|r| r.daily.is_none())
+        .filter(|r| args.source.as_deref().is_none_or(|s| r.matches_source(s)))
+        .filter(|r| args.since.is_none_or(|since| r.local_date() >= since))
+        .filter(|r| args.until.is_none_or(|until| r.local_date() <= until))
+        .collect();
+
+    if records.is_empty() {
+        println!("No sessions found");
+        return Ok(());
+    }
+
+    println!(
+        "{:>5}  {:<16}  {:<28}  {:<8}  {:>5}  {:>5}  {:>5}  {:>7}",
+        "ID", "DATE", "SOURCE", "MODE", "WORDS", "WPM", "ACC", "TIME"
+    );
+    let skip = records.len().saturating_sub(args.limit);
+    for record in records.iter().skip(skip) {
+        println!(
+            "{:>5}  {:<16}  {:<28}  {:<8}  {:>5}  {:>5.0}  {:>4.0}%  {:>6.1}s",
+            record.id,
+            record
+                .timestamp
+                .with_timezone(&Local)
+                .format("%Y-%m-%d %H:%M"),
+            record.source,
+            record.mode,
+            record.words,
+            record.wpm,
+            record.accuracy * 100.0,
+            record.duration,
+        );
+    }
+
+    println!();
+    println!(
+        "{:<10}  {:>8}  {:>7}  {:>7}  {:>7}",
+        "WEEK", "SESSIONS", "AVG WPM", "BEST", "AVG ACC"
+    );
+
+    // runs ended early by a rule are listed, but their speed says nothing about a whole text
+    let completed: Vec<_> = records.iter().copied().filter(|r| r.ended_by.is_none()).collect();
+
+    let mut weeks: BTreeMap<(i32, u32), Vec<&Record>> = BTreeMap::new();
+    for record in &completed {
+        let week = record.local_date().iso_week();
+        weeks
+            .entry((week.year(), week.week()))
+            .or_default()
+            .push(record);
+    }
+
+    for ((year, week), records) in &weeks {
+        let summary = Summary::of(records);
+        println!(
+            "{:<10}  {:>8}  {:>7.0}  {:>7.0}  {:>6.0}%",
+            format!("{year}-W{week:02}"),
+            summary.sessions,
+            summary.average_wpm,
+            summary.best_wpm,
+            summary.average_accuracy * 100.0,
+        );
+    }
+
+    let summary = Summary::of(&completed);
+    println!();
+    println!(
+        "{} sessions ~ {:.0} average wpm ~ {:.0} best wpm ~ {:.0}% average accuracy ~ {:.0} minutes practiced",
+        summary.sessions,
+        summary.average_wpm,
+        summary.best_wpm,
+        summary.average_accuracy * 100.0,
+        summary.total_seconds / 60.0,
+    );
+
+    Ok(())
+}
+
+struct Summary {
+    sessions: usize,
+    average_wpm: f64,
+    best_wpm: f64,
+    average_accuracy: f64,
+    total_seconds: f64,
+}
+
+impl Summary {
+    fn of(records: &[&Record]) -> Self {
+        let sessions = records.len();
+        let count = sessions.max(1) as f64;
+
+        Self {
+            sessions,
+            average_wpm: records.iter().map(|r| r.wpm).sum::<f64>() / count,
+            best_wpm: records.iter().map(|r| r.wpm).fold(0.0, f64::max),
+            average_accuracy: records.iter().map(|r| r.accuracy).sum::<f64>() / count,
+            total_seconds: records.iter().map(|r| r.duration).sum(),
+        }
+    }
+}
@@ -0,0 +1,49 @@
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::stats::millis;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Action {
+    /// A typed character along with the character expected at that position
+    Char {
+        typed: char,
+        expected: Option<char>,
+        correct: bool,
+
+        /// The character was counted but not added to the input because of the error mode
+        #[serde(default)]
+        blocked: bool,
+    },
+
+    /// Removal of the previous character
+    Backspace,
+
+    /// Removal of the previous word (Ctrl+W)
+    BackspaceWord,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Keystroke {
+    /// Time since the session started
+    #[serde(with = "millis")]
+    pub at: Duration,
+
+    /// What the keystroke did
+    pub action: Action,
+}
+
+impl Keystroke {
+    pub fn is_char(&self) -> bool {
+        matches!(self.action, Action::Char { .. })
+    }
+
+    pub fn is_miss(&self) -> bool {
+        matches!(self.action, Action::Char { correct: false, .. })
+    }
+
+    pub fn is_blocked(&self) -> bool {
+        matches!(self.action, Action::Char { blocked: true, .. })
+    }
+}
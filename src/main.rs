@@ -1,26 +1,104 @@
-use clap::Parser;
 use color_eyre::Result;
+use rand::{SeedableRng, rngs::StdRng};
 
-use crate::{config::Config, state::State, text::get_text, ui::run_ui};
+use crate::{
+    app::App,
+    config::{Command, Config},
+    ghost::Ghost,
+    state::State,
+    text::Generator,
+    theme::Theme,
+    ui::run_ui,
+};
 
+mod adaptive;
 mod app;
 mod character;
+mod code;
 mod config;
+mod corpus;
+mod daily;
+mod decorate;
+mod ghost;
+mod heatmap;
+mod history;
+mod keystroke;
 mod markov;
+mod mode;
 mod state;
+mod stats;
+mod syntax;
 mod text;
+mod theme;
 mod ui;
+mod viewport;
 
 fn main() -> Result<()> {
     color_eyre::install()?; // augment errors / panics with easy to read messages
 
-    let config = Config::parse();
+    let config = Config::load()?;
+
+    if let Some(command) = &config.command {
+        return match command {
+            Command::History(args) => history::show(args),
+            Command::Daily(args) => daily::show(args),
+            Command::BuildModel(args) => markov::build_model(args),
+        };
+    }
+
+    let theme = Theme::from_config(&config)?;
 
     loop {
-        let text = get_text(&config)?;
-        let state = State::new(text);
-        let should_loop = run_ui(state, &config)?;
-        if !should_loop {
+        let ghost = config.race.map(Ghost::load).transpose()?;
+
+        let today = daily::today();
+        let seed = if config.daily {
+            daily::seed(today)
+        } else {
+            config.seed.unwrap_or_else(rand::random)
+        };
+        let mut rng = StdRng::seed_from_u64(seed);
+
+        let mut generator = None;
+        let mut state = match &ghost {
+            Some(ghost) => {
+                let mut state = State::new(ghost.target.clone());
+                state.code = ghost.code;
+                state.race_source = Some(ghost.source.clone());
+                state
+            }
+            None if config.daily => {
+                let mut state = State::new(daily::get_text(&mut rng)?);
+                state.seed = Some(seed);
+                state.daily = Some(today);
+                state
+            }
+            None => {
+                // loaded before the UI starts, so notices of model builds stay readable
+                let text_generator = Generator::from_config(&config)?;
+                let mut state = State::new(text_generator.generate(&mut rng)?);
+                state.seed = Some(seed);
+                state.time_limit = config.mode.time_limit();
+                state.code = config.text_source.is_code();
+                state.language = config.text_source.language();
+                generator = Some(text_generator);
+                state
+            }
+        };
+
+        state.error_mode = config.error_mode;
+
+        let mut app = App::new(state, &config, &theme, rng);
+        app.ghost = ghost;
+        app.generator = generator;
+
+        let state = run_ui(app)?;
+
+        if state.is_complete() {
+            history::record_session(&state, &config)?;
+        }
+
+        if !state.should_loop {
             break;
         }
     }
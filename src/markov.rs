@@ -1,108 +1,1026 @@
-use std::collections::HashMap;
+use core::{cmp::Reverse, fmt, str::FromStr};
+use std::{
+    collections::HashMap,
+    fs, mem,
+    path::{Path, PathBuf},
+};
 
-use rand::seq::IndexedRandom;
+use color_eyre::{Result, eyre::eyre};
+use rand::{Rng, seq::{IndexedRandom, WeightError}};
+use serde::{Deserialize, Serialize};
 
-type Key = (String, String);
+use crate::{
+    config::{BuildModelArgs, Config},
+    corpus::{Filters, normalize_punctuation},
+    history::{text_hash, write_atomically},
+};
 
-#[derive(Debug, Default)]
+/// Index of a token in the chain's token table
+type TokenId = u32;
+
+/// The last `order` tokens, which decide the next one, followed by blank tokens
+type Key = [TokenId; MAX_ORDER];
+
+/// The empty token, pads keys and marks word boundaries in character level chains
+const BLANK: TokenId = 0;
+
+/// Longest invented word in character level chains
+const MAX_PSEUDO_WORD_CHARS: usize = 15;
+
+/// Words a sentence level chain may run past `max_words` to finish its last sentence
+const MAX_SENTENCE_OVERRUN: usize = 50;
+
+/// Version of the model file format, bumped on incompatible changes
+const MODEL_VERSION: u32 = 2;
+
+/// What a single state transition of the chain produces
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Level {
+    /// Words following each other, generates sentence-like text
+    Word,
+
+    /// Words with their quotes and brackets, generates whole sentences
+    Sentence,
+
+    /// Characters following each other, generates pronounceable pseudo-words
+    Char,
+}
+
+impl FromStr for Level {
+    type Err = String;
+
+    fn from_str(level: &str) -> core::result::Result<Self, Self::Err> {
+        match level {
+            "word" => Ok(Level::Word),
+            "sentence" => Ok(Level::Sentence),
+            "char" => Ok(Level::Char),
+            _ => Err(format!("unknown markov level {level}, expected word, sentence or char")),
+        }
+    }
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Level::Word => write!(f, "word"),
+            Level::Sentence => write!(f, "sentence"),
+            Level::Char => write!(f, "char"),
+        }
+    }
+}
+
+/// Default number of preceding tokens deciding the next one
+pub const DEFAULT_ORDER: usize = 2;
+
+pub const MAX_ORDER: usize = 5;
+
+/// One or more corpora blended into a single chain,
+/// e.g. `markov:alice.txt*2,markov:tech.txt`
+#[derive(Debug, Clone, PartialEq)]
+pub struct MarkovSource {
+    pub corpora: Vec<Corpus>,
+}
+
+impl FromStr for MarkovSource {
+    type Err = String;
+
+    fn from_str(source: &str) -> core::result::Result<Self, Self::Err> {
+        let corpora = source
+            .split(',')
+            .map(|corpus| {
+                corpus
+                    .strip_prefix("markov:")
+                    .ok_or_else(|| format!("expected markov:<path> in blended markov source, got {corpus}"))?
+                    .parse()
+            })
+            .collect::<core::result::Result<_, _>>()?;
+
+        Ok(MarkovSource { corpora })
+    }
+}
+
+impl fmt::Display for MarkovSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let corpora: Vec<_> = self.corpora.iter().map(|corpus| format!("markov:{corpus}")).collect();
+        write!(f, "{}", corpora.join(","))
+    }
+}
+
+/// Corpus and chain options of a markov text source, e.g. `data/markov.txt?order=3&level=char*2`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Corpus {
+    pub path: String,
+
+    /// Number of preceding tokens deciding the next one, 1..=5
+    pub order: usize,
+
+    pub level: Level,
+
+    /// How many times each transition of the corpus counts when blended with others
+    pub weight: u32,
+}
+
+impl FromStr for Corpus {
+    type Err = String;
+
+    fn from_str(corpus: &str) -> core::result::Result<Self, Self::Err> {
+        let (corpus, weight) = match corpus.rsplit_once('*') {
+            Some((corpus, weight)) => {
+                let weight = weight
+                    .parse()
+                    .ok()
+                    .filter(|&weight| weight > 0)
+                    .ok_or_else(|| format!("markov weight must be a positive whole number, got {weight}"))?;
+                (corpus, weight)
+            }
+            None => (corpus, 1),
+        };
+
+        let (path, options) = corpus.split_once('?').unwrap_or((corpus, ""));
+        if path.is_empty() {
+            return Err("markov source expects file path, .e.g markov:data/markov.txt".to_string());
+        }
+
+        if is_model(path) && !options.is_empty() {
+            return Err(format!(
+                "{path} is a prebuilt model, its options are set by typegym build-model"
+            ));
+        }
+
+        let mut markov = Corpus {
+            path: path.to_string(),
+            order: DEFAULT_ORDER,
+            level: Level::Word,
+            weight,
+        };
+
+        for option in options.split('&').filter(|option| !option.is_empty()) {
+            match option.split_once('=') {
+                Some(("order", order)) => {
+                    markov.order = order
+                        .parse()
+                        .ok()
+                        .filter(|order| (1..=MAX_ORDER).contains(order))
+                        .ok_or_else(|| {
+                            format!("markov order must be between 1 and {MAX_ORDER}, got {order}")
+                        })?;
+                }
+                Some(("level", level)) => markov.level = level.parse()?,
+                _ => {
+                    return Err(format!(
+                        "unknown markov option {option}, expected order=<1..=5> or level=<word|sentence|char>"
+                    ));
+                }
+            }
+        }
+
+        Ok(markov)
+    }
+}
+
+impl fmt::Display for Corpus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut options = Vec::new();
+        if self.order != DEFAULT_ORDER {
+            options.push(format!("order={}", self.order));
+        }
+        if self.level != Level::Word {
+            options.push(format!("level={}", self.level));
+        }
+
+        write!(f, "{}", self.path)?;
+        if !options.is_empty() {
+            write!(f, "?{}", options.join("&"))?;
+        }
+        if self.weight != 1 {
+            write!(f, "*{}", self.weight)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// How the next token is picked among the successors of a key
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sampling {
+    /// Below 1 favors frequent successors even more, above 1 gives rare ones a better chance
+    pub temperature: f64,
+
+    /// Only pick among this many most frequent successors
+    pub top_k: Option<usize>,
+}
+
+impl Default for Sampling {
+    fn default() -> Self {
+        Self {
+            temperature: 1.0,
+            top_k: None,
+        }
+    }
+}
+
+impl Sampling {
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            temperature: config.temperature,
+            top_k: config.top_k,
+        }
+    }
+
+    /// Picks one of the choices with probability following their counts
+    fn pick<T: Copy>(&self, choices: &[(T, u32)], rng: &mut impl Rng) -> Option<T> {
+        if self.temperature == 1.0 && self.top_k.is_none() {
+            match choices.choose_weighted(rng, |choice| choice.1) {
+                Ok(choice) => return Some(choice.0),
+                // counts of heavily weighted corpora may sum past u32, weigh them as floats below
+                Err(WeightError::Overflow) => {}
+                Err(_) => return None,
+            }
+        }
+
+        let mut choices: Vec<_> = choices.iter().collect();
+        if let Some(top_k) = self.top_k {
+            // stable, so equally frequent choices keep their order and seeds stay reproducible
+            choices.sort_by_key(|choice| Reverse(choice.1));
+            choices.truncate(top_k);
+        }
+
+        // relative to the most frequent choice so high counts cannot overflow
+        let max = choices.iter().map(|choice| choice.1).max()? as f64;
+        choices
+            .choose_weighted(rng, |choice| (choice.1 as f64 / max).powf(1.0 / self.temperature))
+            .ok()
+            .map(|choice| choice.0)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
 pub struct MarkovChain {
-    pub transitions: HashMap<Key, Vec<String>>,
-    pub starters: Vec<Key>,
+    pub order: usize,
+    pub level: Level,
+
+    /// Every distinct token, keys and successors refer to tokens by index
+    tokens: Vec<String>,
+
+    #[serde(skip)]
+    ids: HashMap<String, TokenId>,
+
+    /// Successors of every key with the number of times they followed it, sorted by token
+    #[serde(with = "pairs")]
+    transitions: HashMap<Key, Vec<(TokenId, u32)>>,
+
+    /// Opening keys of sentences with their counts, unused by character level chains which start
+    /// from blank keys
+    #[serde(with = "pairs")]
+    starters: HashMap<Key, u32>,
 }
 
 impl MarkovChain {
-    pub fn build(text: &str) -> Self {
-        let mut chain = Self::default();
+    pub fn new(order: usize, level: Level) -> Self {
+        let mut chain = Self {
+            order,
+            level,
+            tokens: Vec::new(),
+            ids: HashMap::new(),
+            transitions: HashMap::new(),
+            starters: HashMap::new(),
+        };
+        chain.intern("");
+        chain
+    }
 
-        for sentence in sentences(text) {
-            let words: Vec<_> = sentence
-                .split_whitespace()
-                .map(cleanup_string)
-                .filter(|w| !w.is_empty())
-                .collect();
+    /// Builds a chain from corpus files cleaned up by the filters, transitions of all of them
+    /// are merged
+    pub fn build_from_files(paths: &[PathBuf], order: usize, level: Level, filters: &Filters) -> Result<Self> {
+        let mut chain = Self::new(order, level);
+        for path in paths {
+            chain.add_text(&filters.apply(&read_corpus(path)?));
+        }
+        Ok(chain)
+    }
 
-            if words.len() < 3 {
-                continue;
+    /// Blends the chains of all corpora of a markov source, weighting their transitions
+    pub fn load(source: &MarkovSource, filters: &Filters) -> Result<Self> {
+        let mut blend: Option<Self> = None;
+
+        for corpus in &source.corpora {
+            let chain = Self::load_corpus(corpus, filters)?;
+            let blend = blend.get_or_insert_with(|| Self::new(chain.order, chain.level));
+
+            if (chain.order, chain.level) != (blend.order, blend.level) {
+                return Err(eyre!(
+                    "cannot blend markov:{corpus} with chains of order {} and level {}, blended chains must have the same order and level",
+                    blend.order,
+                    blend.level
+                ));
             }
+            blend.merge(&chain, corpus.weight);
+        }
+
+        blend.ok_or_else(|| eyre!("markov source has no corpus"))
+    }
+
+    /// Chain of a single corpus, taken from a prebuilt model or the model cache when they are
+    /// newer than the corpus. Text sources are loaded before the UI starts, so the notice of a
+    /// build stays readable
+    fn load_corpus(corpus: &Corpus, filters: &Filters) -> Result<Self> {
+        let path = Path::new(&corpus.path);
+        if is_model(&corpus.path) {
+            return Model::load(path);
+        }
+
+        let source = path.canonicalize().map_err(|e| corpus_error(path, e))?;
+        let cache_path = Model::cache_path(&source, corpus, filters);
+        if let Some(model) = cache_path.as_deref().and_then(Model::read_fresh) {
+            return Ok(model.into_chain());
+        }
+
+        match &cache_path {
+            Some(cache_path) => eprintln!(
+                "Building markov chain of {} into {}",
+                path.display(),
+                cache_path.display()
+            ),
+            None => eprintln!(
+                "Building markov chain of {}, there is no cache directory to keep it in",
+                path.display()
+            ),
+        }
+        let sources = vec![source];
+        let chain = Self::build_from_files(&sources, corpus.order, corpus.level, filters)?;
+        let model = Model::new(chain, sources, filters.clone());
+        model.cache(cache_path.as_deref());
 
-            chain.starters.push((words[0].clone(), words[1].clone()));
+        Ok(model.chain)
+    }
+
+    /// Number of distinct keys with at least one successor
+    pub fn states(&self) -> usize {
+        self.transitions.len()
+    }
+
+    fn intern(&mut self, token: &str) -> TokenId {
+        if let Some(&id) = self.ids.get(token) {
+            return id;
+        }
+
+        let id = self.tokens.len() as TokenId;
+        self.tokens.push(token.to_string());
+        self.ids.insert(token.to_string(), id);
+        id
+    }
+
+    /// Key made of the first `order` tokens, the rest of the key stays blank
+    fn key(&self, tokens: &[TokenId]) -> Key {
+        let mut key = [BLANK; MAX_ORDER];
+        key[..self.order].copy_from_slice(&tokens[..self.order]);
+        key
+    }
+
+    /// Drops the oldest token of the key and appends `next`
+    fn advance(&self, key: &mut Key, next: TokenId) {
+        key.copy_within(1..self.order, 0);
+        key[self.order - 1] = next;
+    }
+
+    fn add_transition(&mut self, key: Key, next: TokenId, count: u32) {
+        let successors = self.transitions.entry(key).or_default();
+        match successors.binary_search_by_key(&next, |successor| successor.0) {
+            Ok(index) => successors[index].1 = successors[index].1.saturating_add(count),
+            Err(index) => successors.insert(index, (next, count)),
+        }
+    }
 
-            for window in words.windows(3) {
-                let key = (window[0].clone(), window[1].clone());
-                chain
-                    .transitions
-                    .entry(key)
-                    .or_default()
-                    .push(window[2].clone());
+    /// Adds the transitions and starters of another chain, each counted `weight` times, counts
+    /// saturate rather than overflow with large weights
+    fn merge(&mut self, other: &Self, weight: u32) {
+        let ids: Vec<_> = other.tokens.iter().map(|token| self.intern(token)).collect();
+        let translate = |key: &Key| -> Key { key.map(|id| ids[id as usize]) };
+
+        for (key, successors) in &other.transitions {
+            let key = translate(key);
+            for &(next, count) in successors {
+                self.add_transition(key, ids[next as usize], count.saturating_mul(weight));
             }
         }
 
-        chain
+        for (key, count) in &other.starters {
+            let starter = self.starters.entry(translate(key)).or_default();
+            *starter = starter.saturating_add(count.saturating_mul(weight));
+        }
     }
 
-    pub fn generate(&self, max_words: usize) -> String {
-        let mut rng = rand::rng();
-        let mut words: Vec<String> = Vec::new();
+    pub fn add_text(&mut self, text: &str) {
+        match self.level {
+            Level::Word => {
+                for sentence in sentences(text) {
+                    let words: Vec<_> = sentence
+                        .split_whitespace()
+                        .map(cleanup_string)
+                        .filter(|w| !w.is_empty())
+                        .collect();
+                    self.add_sentence(&words);
+                }
+            }
+            Level::Sentence => {
+                for sentence in verbatim_sentences(text) {
+                    self.add_sentence(&sentence);
+                }
+            }
+            Level::Char => self.add_words(text),
+        }
+    }
 
-        while words.len() < max_words {
-            let Some(starter) = self.starters.choose(&mut rng) else {
-                return Default::default();
-            };
+    fn add_sentence(&mut self, words: &[String]) {
+        if words.len() <= self.order {
+            return;
+        }
 
-            let (mut previous, mut current) = starter.clone();
+        // sentence level text starts like a sentence, not like the rest of a quote, e.g.
+        // `she said` following `"Nonsense!"`
+        let capitalized = words[0].chars().find(|c| c.is_alphabetic()).is_some_and(char::is_uppercase);
+        let ids: Vec<_> = words.iter().map(|word| self.intern(word)).collect();
+        if self.level != Level::Sentence || capitalized {
+            *self.starters.entry(self.key(&ids)).or_default() += 1;
+        }
+        self.add_windows(&ids);
+    }
 
-            words.push(previous.clone());
-            if words.len() < max_words {
-                words.push(current.clone());
-            } else {
-                break;
+    /// Words are padded with blank tokens, which mark both their start and end
+    fn add_words(&mut self, text: &str) {
+        for word in text.split_whitespace().map(cleanup_string) {
+            let mut tokens = vec![BLANK; self.order];
+            for c in word.chars().filter(|c| c.is_alphabetic()).flat_map(char::to_lowercase) {
+                let id = self.intern(c.encode_utf8(&mut [0; 4]));
+                tokens.push(id);
             }
 
-            loop {
-                let Some(choices) = self.transitions.get(&(previous, current.clone())) else {
-                    break;
-                };
+            if tokens.len() == self.order {
+                continue;
+            }
 
-                let Some(next) = choices.choose(&mut rng) else {
+            tokens.push(BLANK);
+            self.add_windows(&tokens);
+        }
+    }
+
+    fn add_windows(&mut self, tokens: &[TokenId]) {
+        for window in tokens.windows(self.order + 1) {
+            self.add_transition(self.key(window), window[self.order], 1);
+        }
+    }
+
+    /// Generated text, an error when the corpora leave nothing to start from, as empty text would
+    /// end a session right away
+    pub fn generate(&self, max_words: usize, sampling: &Sampling, rng: &mut impl Rng) -> Result<String> {
+        let words = match self.level {
+            Level::Word => self.generate_words(max_words, sampling, rng),
+            Level::Sentence => self.generate_sentences(max_words, sampling, rng),
+            Level::Char => self.generate_pseudo_words(max_words, sampling, rng),
+        };
+
+        let text = if self.level == Level::Sentence {
+            // one sentence per line, reflowing lays them out as a paragraph
+            words.join("\n")
+        } else {
+            let lines: Vec<_> = words.chunks(10).map(|chunk| chunk.join(" ")).collect();
+            lines.join("\n")
+        };
+
+        if text.is_empty() && max_words > 0 {
+            return Err(match self.level {
+                Level::Word => eyre!(
+                    "markov corpus has no sentences longer than the order {}, there is nothing to start text from",
+                    self.order
+                ),
+                Level::Sentence => eyre!(
+                    "markov corpus has no sentence starters, sentence level chains need sentences starting with a capital letter"
+                ),
+                Level::Char => eyre!("markov corpus has no words to build pseudo-words from"),
+            });
+        }
+
+        Ok(text)
+    }
+
+    fn next(&self, key: &Key, sampling: &Sampling, rng: &mut impl Rng) -> Option<TokenId> {
+        sampling.pick(self.transitions.get(key)?, rng)
+    }
+
+    /// Starters in a stable order, hash map order changes between runs and would break seeds
+    fn sorted_starters(&self) -> Vec<(Key, u32)> {
+        let mut starters: Vec<_> = self.starters.iter().map(|(key, count)| (*key, *count)).collect();
+        starters.sort_unstable();
+        starters
+    }
+
+    fn generate_words(&self, max_words: usize, sampling: &Sampling, rng: &mut impl Rng) -> Vec<String> {
+        let starters = self.sorted_starters();
+        let mut words: Vec<&str> = Vec::new();
+
+        while words.len() < max_words {
+            let Some(mut key) = sampling.pick(&starters, rng) else {
+                break;
+            };
+
+            let room = max_words - words.len();
+            words.extend(key[..self.order].iter().take(room).map(|&id| self.token(id)));
+
+            while words.len() < max_words {
+                let Some(next) = self.next(&key, sampling, rng) else {
                     break;
                 };
 
-                if words.len() < max_words {
-                    words.push(next.clone());
-                } else {
+                words.push(self.token(next));
+                self.advance(&mut key, next);
+            }
+        }
+
+        words.into_iter().map(String::from).collect()
+    }
+
+    /// Whole sentences, `max_words` is exceeded to finish the last one
+    fn generate_sentences(&self, max_words: usize, sampling: &Sampling, rng: &mut impl Rng) -> Vec<String> {
+        let starters = self.sorted_starters();
+        let mut sentences = Vec::new();
+        let mut word_count = 0;
+
+        while word_count < max_words {
+            let Some(mut key) = sampling.pick(&starters, rng) else {
+                break;
+            };
+
+            let mut sentence: Vec<_> = key[..self.order].iter().map(|&id| self.token(id)).collect();
+            let mut finished = true;
+
+            while let Some(next) = self.next(&key, sampling, rng) {
+                if word_count + sentence.len() >= max_words + MAX_SENTENCE_OVERRUN {
+                    finished = false;
                     break;
                 }
-                previous = current;
-                current = next.clone();
+
+                sentence.push(self.token(next));
+                self.advance(&mut key, next);
+            }
+
+            if !finished && !sentences.is_empty() {
+                // rather end early than in the middle of a sentence
+                break;
+            }
+
+            word_count += sentence.len();
+            sentences.push(sentence.join(" "));
+        }
+
+        sentences
+    }
+
+    fn generate_pseudo_words(&self, max_words: usize, sampling: &Sampling, rng: &mut impl Rng) -> Vec<String> {
+        let mut words = Vec::new();
+
+        while words.len() < max_words {
+            let mut key = [BLANK; MAX_ORDER];
+            let mut word = String::new();
+
+            while let Some(next) = self.next(&key, sampling, rng)
+                && next != BLANK
+                && word.chars().count() < MAX_PSEUDO_WORD_CHARS
+            {
+                word.push_str(self.token(next));
+                self.advance(&mut key, next);
             }
+
+            if word.is_empty() {
+                // the corpus has no words at all
+                break;
+            }
+            words.push(word);
         }
 
-        let lines: Vec<_> = words.chunks(10).map(|chunk| chunk.join(" ")).collect();
-        lines.join("\n")
+        words
+    }
+
+    fn token(&self, id: TokenId) -> &str {
+        &self.tokens[id as usize]
     }
 }
 
+/// Serializes maps as lists of pairs, JSON only allows strings as map keys
+mod pairs {
+    use std::{collections::HashMap, hash::Hash};
+
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<K: Serialize, V: Serialize, S: Serializer>(
+        map: &HashMap<K, V>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(map)
+    }
+
+    pub fn deserialize<'de, K, V, D>(deserializer: D) -> Result<HashMap<K, V>, D::Error>
+    where
+        K: Deserialize<'de> + Eq + Hash,
+        V: Deserialize<'de>,
+        D: Deserializer<'de>,
+    {
+        Ok(Vec::<(K, V)>::deserialize(deserializer)?.into_iter().collect())
+    }
+}
+
+/// Markov chain saved to disk along with the corpora it was built from
+#[derive(Debug, Serialize, Deserialize)]
+struct Model {
+    version: u32,
+
+    /// Corpus files the model was built from
+    sources: Vec<PathBuf>,
+
+    /// Filters the corpora went through, models built before filters existed used none
+    #[serde(default = "Filters::none")]
+    filters: Filters,
+
+    chain: MarkovChain,
+}
+
+impl Model {
+    fn new(chain: MarkovChain, sources: Vec<PathBuf>, filters: Filters) -> Self {
+        Self {
+            version: MODEL_VERSION,
+            sources,
+            filters,
+            chain,
+        }
+    }
+
+    fn into_chain(self) -> MarkovChain {
+        let mut chain = self.chain;
+        chain.ids = chain
+            .tokens
+            .iter()
+            .enumerate()
+            .map(|(id, token)| (token.clone(), id as TokenId))
+            .collect();
+        chain
+    }
+
+    /// Loads a prebuilt model. When a corpus changed since, the model is rebuilt into the cache
+    /// and the file itself is left untouched
+    fn load(path: &Path) -> Result<MarkovChain> {
+        let contents = fs::read_to_string(path)
+            .map_err(|e| eyre!("could not read markov model {}: {e}", path.display()))?;
+        let model = Self::parse(path, &contents)?;
+        if model.is_fresh(path) || !model.sources.iter().all(|source| source.exists()) {
+            return Ok(model.into_chain());
+        }
+
+        let absolute = path.canonicalize()?;
+        let cache_path = Self::cache_file(&format!("model:{}", absolute.display()));
+        if let Some(rebuilt) = cache_path.as_deref().and_then(Self::read_fresh) {
+            return Ok(rebuilt.into_chain());
+        }
+
+        eprintln!(
+            "Corpora of markov model {} changed since it was built, rebuilding it",
+            path.display()
+        );
+        let chain = MarkovChain::build_from_files(
+            &model.sources,
+            model.chain.order,
+            model.chain.level,
+            &model.filters,
+        )?;
+        let model = Self::new(chain, model.sources, model.filters);
+        model.cache(cache_path.as_deref());
+        Ok(model.chain)
+    }
+
+    /// Reads a cached model of the current version, unless a corpus changed since it was cached
+    fn read_fresh(path: &Path) -> Option<Self> {
+        let contents = fs::read_to_string(path).ok()?;
+        Self::parse(path, &contents).ok().filter(|model| model.is_fresh(path))
+    }
+
+    fn parse(path: &Path, contents: &str) -> Result<Self> {
+        let parse_error = |e| eyre!("could not parse markov model {}: {e}", path.display());
+
+        // check the version first, older models do not parse as the current version
+        let header: ModelHeader = serde_json::from_str(contents).map_err(parse_error)?;
+        if header.version != MODEL_VERSION {
+            return Err(eyre!(
+                "markov model {} has version {}, but only version {MODEL_VERSION} is supported, rebuild it with typegym build-model",
+                path.display(),
+                header.version
+            ));
+        }
+
+        let model: Self = serde_json::from_str(contents).map_err(parse_error)?;
+        if !(1..=MAX_ORDER).contains(&model.chain.order) {
+            return Err(eyre!(
+                "markov model {} has order {}, but the order must be between 1 and {MAX_ORDER}",
+                path.display(),
+                model.chain.order
+            ));
+        }
+
+        Ok(model)
+    }
+
+    fn save(&self, path: &Path) -> Result<()> {
+        write_atomically(path, serde_json::to_string(self)?)
+    }
+
+    /// Saves the model into the cache, if there is one. The cache only saves time, failing to
+    /// write it must not stop the session
+    fn cache(&self, cache_path: Option<&Path>) {
+        if let Some(cache_path) = cache_path {
+            let _ = self.save(cache_path);
+        }
+    }
+
+    /// Whether the model file was written after every corpus it was built from was last changed
+    fn is_fresh(&self, path: &Path) -> bool {
+        let modified = |path: &Path| fs::metadata(path).and_then(|metadata| metadata.modified());
+
+        let Ok(built) = modified(path) else {
+            return false;
+        };
+        self.sources
+            .iter()
+            .all(|source| modified(source).is_ok_and(|changed| changed <= built))
+    }
+
+    /// Cached model of a corpus, keyed by its absolute path, chain options and filters
+    fn cache_path(absolute: &Path, corpus: &Corpus, filters: &Filters) -> Option<PathBuf> {
+        let key = format!(
+            "{}?order={}&level={}&filters={}",
+            absolute.display(),
+            corpus.order,
+            corpus.level,
+            filters.key()
+        );
+
+        Self::cache_file(&key)
+    }
+
+    /// Path of a cached model, none when the platform has no cache directory
+    fn cache_file(key: &str) -> Option<PathBuf> {
+        let cache_dir = dirs::cache_dir()?;
+
+        Some(
+            cache_dir
+                .join("typegym")
+                .join("models")
+                .join(format!("{:016x}.json", text_hash(key))),
+        )
+    }
+}
+
+/// The part of a model file every version has
+#[derive(Debug, Deserialize)]
+struct ModelHeader {
+    version: u32,
+}
+
+/// Whether a markov source path points to a prebuilt model rather than a corpus
+fn is_model(path: &str) -> bool {
+    Path::new(path).extension().is_some_and(|extension| extension == "json")
+}
+
+fn read_corpus(path: &Path) -> Result<String> {
+    fs::read_to_string(path).map_err(|e| corpus_error(path, e))
+}
+
+fn corpus_error(path: &Path, error: std::io::Error) -> color_eyre::Report {
+    eyre!("could not read corpus {}: {error}", path.display())
+}
+
+pub fn build_model(args: &BuildModelArgs) -> Result<()> {
+    if !(1..=MAX_ORDER).contains(&args.order) {
+        return Err(eyre!(
+            "markov order must be between 1 and {MAX_ORDER}, got {}",
+            args.order
+        ));
+    }
+    // markov sources only load paths ending in .json as models, anything else is read as a corpus
+    if !is_model(&args.output.to_string_lossy()) {
+        return Err(eyre!(
+            "model output must be a path ending in .json, got {}",
+            args.output.display()
+        ));
+    }
+
+    let sources = args
+        .corpora
+        .iter()
+        .map(|path| path.canonicalize().map_err(|e| corpus_error(path, e)))
+        .collect::<Result<Vec<_>>>()?;
+
+    let filters = Filters::without(&args.skip_filters);
+    let chain = MarkovChain::build_from_files(&sources, args.order, args.level, &filters)?;
+    let states = chain.states();
+    Model::new(chain, sources, filters).save(&args.output)?;
+
+    println!(
+        "Built {} level model of order {} with {states} states into {}",
+        args.level,
+        args.order,
+        args.output.display()
+    );
+
+    Ok(())
+}
+
 fn cleanup_string(string: &str) -> String {
-    let new_string: String = string
-        .chars()
-        .map(|c| match c {
-            '“' => '"',
-            '”' => '"',
-            '’' => '\'',
-            '‘' => '\'',
-            '—' => '-',
-            ch => ch
-        })
-        .collect();
-
-    new_string
+    normalize_punctuation(string)
         .trim_matches(|c| matches!(c, ' ' | '"' | '\'' | '_' | '(' | ')' | '[' | ']'))
         .to_string()
 }
 
 fn sentences(text: &str) -> Vec<String> {
-    text.split_inclusive(|c| c == '.' || c == '!' || c == '?')
+    text.split_inclusive(['.', '!', '?'])
         .map(cleanup_string)
         .filter(|s| !s.is_empty())
         .collect()
 }
+
+/// Sentences as lists of words keeping their quotes and brackets, a sentence ends after a word
+/// ending in `.`, `!` or `?`, possibly followed by closing quotes or brackets
+fn verbatim_sentences(text: &str) -> Vec<Vec<String>> {
+    let mut sentences = Vec::new();
+    let mut sentence = Vec::new();
+
+    for word in text.split_whitespace() {
+        // underscores mark italics in plain text books
+        let word = normalize_punctuation(word).replace('_', "");
+        if word.is_empty() {
+            continue;
+        }
+
+        let ends_sentence = word
+            .trim_end_matches(['"', '\'', ')', ']'])
+            .ends_with(['.', '!', '?']);
+        sentence.push(word);
+
+        if ends_sentence {
+            sentences.push(mem::take(&mut sentence));
+        }
+    }
+
+    if !sentence.is_empty() {
+        sentences.push(sentence);
+    }
+
+    sentences
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{SeedableRng, rngs::StdRng};
+
+    use super::*;
+
+    const CORPUS: &str = "The cat sat on the mat. The cat ate the rat. \
+        The dog sat on the log! Did the dog see the cat? The rat ran off.";
+
+    fn chain(order: usize, level: Level) -> MarkovChain {
+        let mut chain = MarkovChain::new(order, level);
+        chain.add_text(CORPUS);
+        chain
+    }
+
+    fn generate(chain: &MarkovChain, seed: u64) -> String {
+        chain.generate(12, &Sampling::default(), &mut StdRng::seed_from_u64(seed)).unwrap()
+    }
+
+    #[test]
+    fn word_chain_is_pinned_by_the_seed() {
+        assert_eq!(
+            generate(&chain(1, Level::Word), 7),
+            "The cat sat on the mat. The dog see the\nlog! Did"
+        );
+        assert_eq!(
+            generate(&chain(2, Level::Word), 7),
+            "The dog sat on the mat. The cat ate the\nrat. The"
+        );
+    }
+
+    #[test]
+    fn char_chain_is_pinned_by_the_seed() {
+        assert_eq!(
+            generate(&chain(2, Level::Char), 7),
+            "cat the on dog the mat the cat dog sat\non the"
+        );
+    }
+
+    fn picks(sampling: &Sampling, choices: &[(char, u32)]) -> HashMap<char, usize> {
+        let mut rng = StdRng::seed_from_u64(1);
+        let mut picks = HashMap::new();
+        for _ in 0..1000 {
+            *picks.entry(sampling.pick(choices, &mut rng).unwrap()).or_default() += 1;
+        }
+        picks
+    }
+
+    #[test]
+    fn top_k_only_picks_the_most_frequent() {
+        let sampling = Sampling { temperature: 1.0, top_k: Some(2) };
+        let picks = picks(&sampling, &[('a', 5), ('b', 1), ('c', 3)]);
+        assert!(picks.contains_key(&'a') && picks.contains_key(&'c'));
+        assert!(!picks.contains_key(&'b'));
+    }
+
+    #[test]
+    fn top_k_keeps_the_first_of_equally_frequent_choices() {
+        let sampling = Sampling { temperature: 1.0, top_k: Some(1) };
+        let picks = picks(&sampling, &[('a', 1), ('b', 4), ('c', 4)]);
+        assert_eq!(picks.get(&'b'), Some(&1000));
+    }
+
+    #[test]
+    fn temperature_sharpens_and_flattens_the_distribution() {
+        let choices = [('a', 9), ('b', 1)];
+        let default = picks(&Sampling::default(), &choices)[&'b'];
+        let cold = picks(&Sampling { temperature: 0.2, top_k: None }, &choices)
+            .get(&'b')
+            .copied()
+            .unwrap_or(0);
+        let hot = picks(&Sampling { temperature: 10.0, top_k: None }, &choices)[&'b'];
+
+        assert!(cold < 5, "rare choice picked {cold} times when cold");
+        assert!((50..200).contains(&default), "rare choice picked {default} times");
+        assert!(hot > 350, "rare choice picked {hot} times when hot");
+    }
+
+    #[test]
+    fn blended_sources_keep_their_weights() {
+        let source: MarkovSource = "markov:alice.txt*2,markov:tech.txt".parse().unwrap();
+        let weights: Vec<_> = source.corpora.iter().map(|corpus| corpus.weight).collect();
+        assert_eq!(weights, [2, 1]);
+        assert_eq!(source.to_string(), "markov:alice.txt*2,markov:tech.txt");
+    }
+
+    #[test]
+    fn large_weights_saturate() {
+        let corpus = chain(2, Level::Word);
+        let mut blend = MarkovChain::new(2, Level::Word);
+        blend.merge(&corpus, u32::MAX);
+        blend.merge(&corpus, u32::MAX);
+
+        assert!(blend.starters.values().all(|&count| count == u32::MAX));
+        assert!(!generate(&blend, 7).is_empty());
+    }
+
+    #[test]
+    fn sentence_chain_of_a_lowercase_corpus_has_no_starters() {
+        let mut chain = MarkovChain::new(2, Level::Sentence);
+        chain.add_text(&CORPUS.to_lowercase());
+
+        let error = chain.generate(12, &Sampling::default(), &mut StdRng::seed_from_u64(7)).unwrap_err();
+        assert!(error.to_string().contains("no sentence starters"), "{error}");
+
+        let mut chain = MarkovChain::new(2, Level::Word);
+        chain.add_text(&CORPUS.to_lowercase());
+        assert!(!generate(&chain, 7).is_empty());
+    }
+
+    #[test]
+    fn empty_corpus_generates_an_error_rather_than_empty_text() {
+        let chain = MarkovChain::new(2, Level::Char);
+        assert!(chain.generate(12, &Sampling::default(), &mut StdRng::seed_from_u64(7)).is_err());
+    }
+
+    #[test]
+    fn same_seed_generates_the_same_text() {
+        let chain = chain(2, Level::Word);
+        assert_eq!(generate(&chain, 3), generate(&chain, 3));
+    }
+
+    #[test]
+    fn models_with_an_invalid_order_are_rejected() {
+        for order in [0, MAX_ORDER + 1] {
+            let model = Model::new(MarkovChain::new(order, Level::Word), Vec::new(), Filters::none());
+            let contents = serde_json::to_string(&model).unwrap();
+            let error = Model::parse(Path::new("broken.json"), &contents).unwrap_err();
+            assert!(error.to_string().contains("order must be between 1 and 5"), "{error}");
+        }
+
+        let model = Model::new(chain(2, Level::Word), Vec::new(), Filters::none());
+        let contents = serde_json::to_string(&model).unwrap();
+        assert!(Model::parse(Path::new("model.json"), &contents).is_ok());
+    }
+
+    #[test]
+    fn models_are_only_built_into_json_files() {
+        let args = BuildModelArgs {
+            corpora: vec![PathBuf::from("data/markov.txt")],
+            output: PathBuf::from("alice.model"),
+            order: DEFAULT_ORDER,
+            level: Level::Word,
+            skip_filters: Vec::new(),
+        };
+        let error = build_model(&args).unwrap_err();
+        assert!(error.to_string().contains("ending in .json"), "{error}");
+    }
+}
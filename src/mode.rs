@@ -0,0 +1,48 @@
+use core::{fmt, str::FromStr};
+use std::time::Duration;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Mode {
+    /// Session ends once the whole text is typed
+    Words,
+
+    /// Session ends once the time runs out, text is generated endlessly
+    Time(Duration),
+}
+
+impl Mode {
+    pub fn time_limit(&self) -> Option<Duration> {
+        match self {
+            Mode::Words => None,
+            Mode::Time(limit) => Some(*limit),
+        }
+    }
+}
+
+impl FromStr for Mode {
+    type Err = String;
+
+    fn from_str(mode: &str) -> core::result::Result<Self, Self::Err> {
+        if mode == "words" {
+            return Ok(Mode::Words);
+        }
+
+        let Some(seconds) = mode.strip_prefix("time:") else {
+            return Err("expected words or time:<secs>, e.g. time:60".to_string());
+        };
+
+        match seconds.parse::<u64>() {
+            Ok(seconds) if seconds > 0 => Ok(Mode::Time(Duration::from_secs(seconds))),
+            _ => Err("time mode expects a positive number of seconds, e.g. time:15, time:30, time:60 or time:120".to_string()),
+        }
+    }
+}
+
+impl fmt::Display for Mode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Mode::Words => write!(f, "words"),
+            Mode::Time(limit) => write!(f, "time:{}", limit.as_secs()),
+        }
+    }
+}
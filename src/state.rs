@@ -1,10 +1,24 @@
-use std::{iter::repeat, time::Instant};
+use std::{
+    iter::repeat,
+    time::{Duration, Instant},
+};
 
-use crate::character::{Character, classify_character};
+use chrono::NaiveDate;
+
+use crate::{
+    character::{Character, classify_character},
+    config::{ErrorMode, Rule},
+    keystroke::{Action, Keystroke},
+    syntax::{Annotations, Language, annotate},
+    text::reflow_continued,
+};
 
 pub type Line = Vec<Character>;
 pub type Page = Vec<Line>;
 
+/// Number of untyped lines below which more text is appended in time mode
+const MIN_LINES_AHEAD: usize = 2;
+
 #[derive(Debug)]
 pub struct State {
     /// The text user needs to type
@@ -19,14 +33,41 @@ pub struct State {
     /// The time at which practice session finished (was complete)
     pub session_end: Option<Instant>,
 
-    /// The number of keystrokes user did
-    pub strokes: u64,
-
     /// The number of correct keystrokes
     pub hits: u64,
 
+    /// How mistakes affect the cursor
+    pub error_mode: ErrorMode,
+
+    /// Code practice, whitespace is typed exactly and Enter fills in the indentation
+    pub code: bool,
+
+    /// Language of the code being typed, used for syntax highlighting
+    pub language: Option<Language>,
+
+    /// The rule which ended the session early
+    pub ended_by: Option<Rule>,
+
+    /// Number of times the session was restarted from scratch
+    pub restarts: u32,
+
+    /// Seed the target text was generated with, `None` when it was not generated
+    pub seed: Option<u64>,
+
+    /// Date of the daily challenge this session belongs to
+    pub daily: Option<NaiveDate>,
+
+    /// Text source of the run raced against, recorded instead of the configured one
+    pub race_source: Option<String>,
+
+    /// Session duration in time mode, `None` when session ends once the text is typed
+    pub time_limit: Option<Duration>,
+
     /// A flag indicating whether we want to start another practice session
     pub should_loop: bool,
+
+    /// Every keystroke applied during the session, in order
+    keystrokes: Vec<Keystroke>,
 }
 
 impl State {
@@ -36,9 +77,18 @@ impl State {
             input: "".to_string(),
             session_start: None,
             session_end: None,
-            strokes: 0,
             hits: 0,
+            error_mode: ErrorMode::Free,
+            code: false,
+            language: None,
+            ended_by: None,
+            restarts: 0,
+            seed: None,
+            daily: None,
+            race_source: None,
+            time_limit: None,
             should_loop: false,
+            keystrokes: Vec::new(),
         }
     }
 
@@ -50,7 +100,29 @@ impl State {
             return;
         }
 
-        let to_append = if c.is_whitespace() {
+        let expected = self.target.chars().nth(input_count);
+        let correct = match expected {
+            // Tab fills in the indentation, whatever it is made of
+            Some(e) if self.code => e == c || (c == '\t' && (e == ' ' || e == '\t')),
+            Some(e) => e == c || (e.is_whitespace() && c.is_whitespace()),
+            None => false,
+        };
+
+        let blocked = match self.error_mode {
+            ErrorMode::Free => false,
+            ErrorMode::Stop => !correct,
+            ErrorMode::Word => c.is_whitespace() && self.current_word_has_errors(),
+        };
+
+        if blocked {
+            // the stroke still counts, it just does not move the cursor
+            self.record(Action::Char { typed: c, expected, correct, blocked });
+            return;
+        }
+
+        let to_append = if self.code {
+            self.code_input(c, input_count, correct)
+        } else if c.is_whitespace() {
             let ws: String = self
                 .target
                 .chars()
@@ -64,10 +136,45 @@ impl State {
 
         self.input.push_str(&to_append);
 
-        self.strokes += 1;
         if self.is_error_free() {
             self.hits += 1;
         }
+
+        self.record(Action::Char { typed: c, expected, correct, blocked });
+    }
+
+    /// Whether the word being typed (since the last whitespace) contains mistakes
+    fn current_word_has_errors(&self) -> bool {
+        let input_count = self.input.chars().count();
+        let word_length = self.input.chars().rev().take_while(|c| !c.is_whitespace()).count();
+        let word_start = input_count - word_length;
+
+        self.input
+            .chars()
+            .skip(word_start)
+            .zip(self.target.chars().skip(word_start).map(Some).chain(repeat(None)))
+            .any(|(i, t)| Some(i) != t)
+    }
+
+    /// Text appended for a keystroke in code practice, whitespace is only
+    /// taken as a run after Enter and Tab, like an editor's auto indentation
+    fn code_input(&self, c: char, input_count: usize, correct: bool) -> String {
+        let indentation = |skip| -> String {
+            self.target
+                .chars()
+                .skip(skip)
+                .take_while(|&c| c == ' ' || c == '\t')
+                .collect()
+        };
+
+        match c {
+            '\n' if correct => format!("\n{}", indentation(input_count + 1)),
+            '\t' if correct => indentation(input_count),
+            // keep mistyped line breaks and tabs visible without breaking the line layout
+            '\n' => "↵".to_string(),
+            '\t' => "→".to_string(),
+            _ => c.to_string(),
+        }
     }
 
     pub fn apply_backspace(&mut self) {
@@ -75,6 +182,11 @@ impl State {
             return;
         }
 
+        if self.code {
+            self.apply_backspace_code();
+            return;
+        }
+
         let matching_count = self.input
             .chars()
             .zip(self.target.chars())
@@ -87,6 +199,25 @@ impl State {
         let to_remove = matching_count.max(1);
         let new_len = self.input.chars().count().saturating_sub(to_remove);
         self.input = self.input.chars().take(new_len).collect();
+
+        self.record(Action::Backspace);
+    }
+
+    /// Removes a line break together with the indentation filled in after it
+    fn apply_backspace_code(&mut self) {
+        let indentation = self
+            .input
+            .chars()
+            .rev()
+            .take_while(|&c| c == ' ' || c == '\t')
+            .count();
+        let after_line_break = self.input.chars().rev().nth(indentation) == Some('\n');
+
+        let to_remove = if after_line_break { indentation + 1 } else { 1 };
+        let new_len = self.input.chars().count().saturating_sub(to_remove);
+        self.input = self.input.chars().take(new_len).collect();
+
+        self.record(Action::Backspace);
     }
 
     pub fn apply_backspace_word(&mut self) {
@@ -114,6 +245,41 @@ impl State {
 
         let new_len = self.input.chars().count().saturating_sub(count);
         self.input = self.input.chars().take(new_len).collect();
+
+        self.record(Action::BackspaceWord);
+    }
+
+    /// Applies a previously recorded keystroke
+    pub fn replay(&mut self, keystroke: &Keystroke) {
+        match keystroke.action {
+            Action::Char { blocked: true, .. } => self.record(keystroke.action.clone()),
+            Action::Char { typed, .. } => self.apply_char(typed),
+            Action::Backspace => self.apply_backspace(),
+            Action::BackspaceWord => self.apply_backspace_word(),
+        }
+    }
+
+    fn record(&mut self, action: Action) {
+        let at = self
+            .session_start
+            .map(|start| start.elapsed())
+            .unwrap_or(Duration::ZERO);
+        self.keystrokes.push(Keystroke { at, action });
+    }
+
+    /// All keystrokes of the session in the order they were applied
+    pub fn keystrokes(&self) -> impl Iterator<Item = &Keystroke> {
+        self.keystrokes.iter()
+    }
+
+    /// Typed characters only, skipping backspaces and word deletions
+    pub fn typed_keystrokes(&self) -> impl Iterator<Item = &Keystroke> {
+        self.keystrokes().filter(|k| k.is_char())
+    }
+
+    /// Typed characters that made it into the input, leaving out those blocked by the error mode
+    fn entered_keystrokes(&self) -> impl Iterator<Item = &Keystroke> {
+        self.typed_keystrokes().filter(|k| !k.is_blocked())
     }
 
     fn is_error_free(&self) -> bool {
@@ -121,7 +287,81 @@ impl State {
     }
 
     pub fn is_complete(&self) -> bool {
-        self.target == self.input
+        if self.ended_by.is_some() {
+            return true;
+        }
+
+        match self.time_limit {
+            Some(_) => self.session_end.is_some(),
+            None => self.target == self.input,
+        }
+    }
+
+    /// Ends the session early because a rule was broken
+    pub fn end_by(&mut self, rule: Rule) {
+        self.ended_by = Some(rule);
+        self.stop_clock();
+    }
+
+    /// Starts typing the same text again from scratch, the clock of a timed session keeps running
+    pub fn restart(&mut self) {
+        self.input.clear();
+        if self.time_limit.is_none() {
+            self.session_start = None;
+        }
+        self.session_end = None;
+        self.hits = 0;
+        self.keystrokes.clear();
+        self.restarts += 1;
+    }
+
+    pub fn is_time_up(&self) -> bool {
+        match (self.session_start, self.time_limit) {
+            (Some(start), Some(limit)) => start.elapsed() >= limit,
+            _ => false,
+        }
+    }
+
+    /// Whether the user is close enough to the end of the target to append more text
+    pub fn needs_more_text(&self) -> bool {
+        self.time_limit.is_some()
+            && self.target.lines().count().saturating_sub(self.cursor_row()) <= MIN_LINES_AHEAD
+    }
+
+    /// Reflows the untyped part of the target, leaving typed text and the cursor where they are
+    pub fn reflow_remaining(&mut self, width: usize) {
+        let input_count = self.input.chars().count();
+        let mut rest = self.target.chars().skip(input_count).peekable();
+
+        // the word under the cursor stays on its line
+        let mut kept: String = self.target.chars().take(input_count).collect();
+        while let Some(c) = rest.next_if(|c| !c.is_whitespace()) {
+            kept.push(c);
+        }
+
+        let remaining: String = rest.collect();
+        if remaining.trim().is_empty() {
+            return;
+        }
+
+        let column = kept.chars().rev().take_while(|&c| c != '\n').count();
+        kept.push_str(&reflow_continued(&remaining, width, column));
+        self.target = kept;
+    }
+
+    pub fn extend_target(&mut self, text: &str) {
+        if text.is_empty() {
+            return;
+        }
+
+        if !self.target.is_empty() {
+            self.target.push('\n');
+            if self.code {
+                // keep a blank line between snippets
+                self.target.push('\n');
+            }
+        }
+        self.target.push_str(text);
     }
 
     pub fn has_started(&self) -> bool {
@@ -133,7 +373,11 @@ impl State {
     }
 
     pub fn stop_clock(&mut self) {
-        self.session_end = Some(Instant::now());
+        let now = Instant::now();
+        self.session_end = Some(match (self.session_start, self.time_limit) {
+            (Some(start), Some(limit)) => now.min(start + limit),
+            _ => now,
+        });
     }
 
     fn build_line(&self, target: &str, input: &str) -> Line {
@@ -163,6 +407,13 @@ impl State {
             .collect()
     }
 
+    /// Token classes of the target text, empty when it is not highlighted
+    pub fn build_annotations(&self) -> Annotations {
+        self.language
+            .map(|language| annotate(&self.target, language))
+            .unwrap_or_default()
+    }
+
     pub fn cursor_row(&self) -> usize {
         self.input.chars().filter(|&c| c == '\n').count()
     }
@@ -175,18 +426,45 @@ impl State {
         (self.cursor_row(), self.cursor_col())
     }
 
-    fn elapsed_seconds(&self) -> f64 {
+    pub fn elapsed(&self) -> Duration {
         match (self.session_start, self.session_end) {
-            (Some(start), Some(end)) => end.duration_since(start).as_secs_f64(),
-            _ => 0.0
+            (Some(start), Some(end)) => end.duration_since(start),
+            (Some(start), None) => start.elapsed(),
+            _ => Duration::ZERO,
         }
     }
 
+    pub fn elapsed_seconds(&self) -> f64 {
+        self.elapsed().as_secs_f64()
+    }
+
+    /// Session progress in range 0.0..=1.0, by time in time mode and by typed text otherwise
+    pub fn progress(&self) -> f64 {
+        if let Some(limit) = self.time_limit {
+            return (self.elapsed_seconds() / limit.as_secs_f64()).min(1.0);
+        }
+
+        let target_count = self.target.chars().count();
+        if target_count == 0 {
+            return 1.0;
+        }
+
+        (self.input.chars().count() as f64 / target_count as f64).min(1.0)
+    }
+
+    /// Number of correctly typed characters, whitespace groups count as one character
     fn chars_count(&self) -> usize {
         let mut count = 0;
         let mut in_whitespace_group = false;
 
-        for c in self.target.chars() {
+        let correct = self
+            .input
+            .chars()
+            .zip(self.target.chars())
+            .filter(|(i, t)| i == t)
+            .map(|(i, _)| i);
+
+        for c in correct {
             if c.is_whitespace() {
                 if !in_whitespace_group {
                     count += 1;
@@ -211,11 +489,187 @@ impl State {
         (chars / 5.0) / (seconds / 60.0)
     }
 
+    /// Words per minute counting every typed character, correct or not
+    pub fn raw_wpm(&self) -> f64 {
+        let seconds = self.elapsed_seconds();
+        if seconds == 0.0 {
+            return 0.0;
+        }
+
+        let chars = self.entered_keystrokes().count() as f64;
+        (chars / 5.0) / (seconds / 60.0)
+    }
+
+    /// Raw words per minute minus uncorrected errors per minute
+    pub fn net_wpm(&self) -> f64 {
+        let seconds = self.elapsed_seconds();
+        if seconds == 0.0 {
+            return 0.0;
+        }
+
+        let errors_per_minute = self.uncorrected_errors() as f64 / (seconds / 60.0);
+        (self.raw_wpm() - errors_per_minute).max(0.0)
+    }
+
+    /// Number of mistakes left in the input
+    pub fn uncorrected_errors(&self) -> usize {
+        let target_count = self.target.chars().count();
+        let mismatched = self
+            .input
+            .chars()
+            .zip(self.target.chars())
+            .filter(|(i, t)| i != t)
+            .count();
+        let overflow = self.input.chars().count().saturating_sub(target_count);
+
+        mismatched + overflow
+    }
+
+    /// Number of mistakes made and later fixed, blocked mistakes were never entered
+    pub fn corrected_errors(&self) -> usize {
+        let misses = self.entered_keystrokes().filter(|k| k.is_miss()).count();
+        misses.saturating_sub(self.uncorrected_errors())
+    }
+
+    pub fn keystrokes_per_second(&self) -> f64 {
+        let seconds = self.elapsed_seconds();
+        if seconds == 0.0 {
+            return 0.0;
+        }
+
+        self.keystrokes().count() as f64 / seconds
+    }
+
     pub fn accuracy(&self) -> f64 {
-        if self.strokes == 0 {
+        let strokes = self.typed_keystrokes().count();
+        if strokes == 0 {
             return 1.0;
         }
 
-        self.hits as f64 / self.strokes as f64
+        self.hits as f64 / strokes as f64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn typed(text: &str, error_mode: ErrorMode, keys: &str) -> State {
+        let mut state = State::new(text.to_string());
+        state.error_mode = error_mode;
+        for c in keys.chars() {
+            state.apply_char(c);
+        }
+        state
+    }
+
+    #[test]
+    fn free_mode_types_mistakes() {
+        let state = typed("cat dog", ErrorMode::Free, "cxt d");
+        assert_eq!(state.input, "cxt d");
+        assert_eq!(state.uncorrected_errors(), 1);
+    }
+
+    #[test]
+    fn stop_mode_waits_for_the_correct_key() {
+        let state = typed("cat", ErrorMode::Stop, "cxxat");
+        assert_eq!(state.input, "cat");
+        assert!(state.is_complete());
+        assert_eq!(state.typed_keystrokes().count(), 5);
+        assert_eq!(state.keystrokes().filter(|k| k.is_miss()).count(), 2);
+    }
+
+    #[test]
+    fn blocked_mistakes_are_neither_corrected_nor_entered() {
+        let state = typed("cat", ErrorMode::Stop, "cxxat");
+        assert_eq!(state.corrected_errors(), 0);
+        assert_eq!(state.entered_keystrokes().count(), 3);
+        assert!(state.accuracy() < 1.0);
+
+        let mut state = typed("cat", ErrorMode::Free, "cx");
+        state.apply_backspace();
+        for c in "at".chars() {
+            state.apply_char(c);
+        }
+        assert_eq!(state.corrected_errors(), 1);
+    }
+
+    #[test]
+    fn word_mode_blocks_space_after_a_mistyped_word() {
+        let mut state = typed("cat dog", ErrorMode::Word, "cxt ");
+        assert_eq!(state.input, "cxt");
+
+        state.apply_backspace();
+        state.apply_backspace();
+        for c in "at d".chars() {
+            state.apply_char(c);
+        }
+        assert_eq!(state.input, "cat d");
+    }
+
+    #[test]
+    fn word_mode_allows_mistakes_inside_a_word() {
+        let state = typed("cat", ErrorMode::Word, "cxt");
+        assert_eq!(state.input, "cxt");
+    }
+
+    #[test]
+    fn reflow_keeps_the_word_under_the_cursor_on_its_line() {
+        let mut state = typed("the quick brown fox", ErrorMode::Free, "the qu");
+        state.reflow_remaining(12);
+        assert_eq!(state.target, "the quick\nbrown fox");
+        assert_eq!(state.input, "the qu");
+    }
+
+    #[test]
+    fn reflow_leaves_fully_typed_text_alone() {
+        let mut state = typed("the quick", ErrorMode::Free, "the qu");
+        state.reflow_remaining(4);
+        assert_eq!(state.target, "the quick");
+    }
+
+    #[test]
+    fn reflow_joins_lines_when_the_terminal_grows() {
+        let mut state = typed("the\nquick\nbrown fox", ErrorMode::Free, "t");
+        state.reflow_remaining(40);
+        assert_eq!(state.target, "the quick brown fox");
+    }
+
+    fn code(text: &str, keys: &str) -> State {
+        let mut state = State::new(text.to_string());
+        state.code = true;
+        for c in keys.chars() {
+            state.apply_char(c);
+        }
+        state
+    }
+
+    #[test]
+    fn enter_fills_in_the_indentation_of_the_next_line() {
+        let state = code("fn a() {\n    b\n}", "fn a() {\n");
+        assert_eq!(state.input, "fn a() {\n    ");
+        assert_eq!(state.uncorrected_errors(), 0);
+    }
+
+    #[test]
+    fn backspace_removes_the_line_break_with_its_indentation() {
+        let mut state = code("fn a() {\n    b\n}", "fn a() {\n");
+        state.apply_backspace();
+        assert_eq!(state.input, "fn a() {");
+
+        let mut state = code("fn a() {\n    b\n}", "fn a() {\nb");
+        state.apply_backspace();
+        assert_eq!(state.input, "fn a() {\n    ");
+    }
+
+    #[test]
+    fn tab_fills_in_indentation_and_space_types_it_one_by_one() {
+        assert_eq!(code("    b", "\t").input, "    ");
+        assert_eq!(code("    b", " ").input, " ");
+    }
+
+    #[test]
+    fn mistyped_line_breaks_and_tabs_stay_visible() {
+        assert_eq!(code("ab", "\n\t").input, "↵→");
     }
 }
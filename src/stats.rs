@@ -0,0 +1,251 @@
+use std::{collections::BTreeMap, ops::AddAssign, time::Duration};
+
+use serde::{Deserialize, Serialize};
+
+use crate::keystroke::{Action, Keystroke};
+
+#[derive(Debug, Default, Clone, Copy, Serialize, Deserialize)]
+pub struct KeyStats {
+    /// Number of times the key was expected
+    pub presses: u32,
+
+    /// Number of times something else was typed instead
+    pub misses: u32,
+
+    /// Sum of the times between the previous keystroke and this one
+    #[serde(rename = "latency_ms", with = "millis")]
+    pub total_latency: Duration,
+
+    /// Number of presses with a latency, the first keystroke of a session has none
+    #[serde(default)]
+    pub latency_samples: u32,
+}
+
+impl AddAssign for KeyStats {
+    fn add_assign(&mut self, other: Self) {
+        self.presses += other.presses;
+        self.misses += other.misses;
+        self.total_latency += other.total_latency;
+        self.latency_samples += other.latency_samples;
+    }
+}
+
+impl KeyStats {
+    pub fn miss_rate(&self) -> f64 {
+        if self.presses == 0 {
+            return 0.0;
+        }
+
+        self.misses as f64 / self.presses as f64
+    }
+
+    /// Zero when no press had a latency
+    pub fn average_latency(&self) -> Duration {
+        if self.latency_samples == 0 {
+            return Duration::ZERO;
+        }
+
+        self.total_latency / self.latency_samples
+    }
+}
+
+/// Maps a character to the physical key producing it, e.g. `A` and `a` both map to `a`
+pub fn physical_key(c: char) -> char {
+    match c {
+        c if c.is_whitespace() => ' ',
+        '~' => '`',
+        '!' => '1',
+        '@' => '2',
+        '#' => '3',
+        '$' => '4',
+        '%' => '5',
+        '^' => '6',
+        '&' => '7',
+        '*' => '8',
+        '(' => '9',
+        ')' => '0',
+        '_' => '-',
+        '+' => '=',
+        '{' => '[',
+        '}' => ']',
+        '|' => '\\',
+        ':' => ';',
+        '"' => '\'',
+        '<' => ',',
+        '>' => '.',
+        '?' => '/',
+        c => c.to_ascii_lowercase(),
+    }
+}
+
+/// Per key miss counts and latencies, attributed to the key that was expected. Strokes blocked
+/// by the error mode count as misses of the expected key, without a latency of their own
+pub fn key_stats<'a>(keystrokes: impl Iterator<Item = &'a Keystroke>) -> BTreeMap<char, KeyStats> {
+    let mut stats: BTreeMap<char, KeyStats> = BTreeMap::new();
+
+    for stroke in typed(keystrokes) {
+        let entry = stats.entry(physical_key(stroke.expected)).or_default();
+        entry.add(stroke.correct, stroke.latency);
+    }
+
+    stats
+}
+
+/// Miss counts and latencies of consecutively typed character pairs, e.g. `th`. Blocked strokes
+/// are skipped, retrying the same character would pair it with itself
+pub fn bigram_stats<'a>(
+    keystrokes: impl Iterator<Item = &'a Keystroke>,
+) -> BTreeMap<String, KeyStats> {
+    let mut stats: BTreeMap<String, KeyStats> = BTreeMap::new();
+
+    for stroke in typed(keystrokes).filter(|stroke| !stroke.blocked) {
+        let Some(previous) = stroke.previous else {
+            continue;
+        };
+
+        if previous.is_whitespace() || stroke.expected.is_whitespace() {
+            continue;
+        }
+
+        let bigram: String = [previous, stroke.expected]
+            .iter()
+            .flat_map(|c| c.to_lowercase())
+            .collect();
+        stats.entry(bigram).or_default().add(stroke.correct, stroke.latency);
+    }
+
+    stats
+}
+
+impl KeyStats {
+    fn add(&mut self, correct: bool, latency: Option<Duration>) {
+        self.presses += 1;
+        if !correct {
+            self.misses += 1;
+        }
+        if let Some(latency) = latency {
+            self.total_latency += latency;
+            self.latency_samples += 1;
+        }
+    }
+}
+
+/// A typed character as seen by the stats
+struct Typed {
+    expected: char,
+
+    /// The character expected before this one, unknown after a deletion
+    previous: Option<char>,
+
+    correct: bool,
+
+    /// Whether the error mode kept the character out of the input
+    blocked: bool,
+
+    /// Time since the previous stroke that moved the cursor, so the time spent on blocked
+    /// retries counts toward the stroke that finally gets through
+    latency: Option<Duration>,
+}
+
+fn typed<'a>(keystrokes: impl Iterator<Item = &'a Keystroke>) -> impl Iterator<Item = Typed> {
+    let mut previous_at: Option<Duration> = None;
+    let mut previous_expected: Option<char> = None;
+
+    keystrokes.filter_map(move |keystroke| match keystroke.action {
+        Action::Char {
+            expected: Some(expected),
+            correct,
+            blocked: true,
+            ..
+        } => Some(Typed {
+            expected,
+            previous: previous_expected,
+            correct,
+            blocked: true,
+            latency: None,
+        }),
+        Action::Char {
+            expected: Some(expected),
+            correct,
+            ..
+        } => {
+            let latency = previous_at.map(|at| keystroke.at.saturating_sub(at));
+            previous_at = Some(keystroke.at);
+
+            Some(Typed {
+                expected,
+                previous: previous_expected.replace(expected),
+                correct,
+                blocked: false,
+                latency,
+            })
+        }
+        _ => {
+            previous_at = Some(keystroke.at);
+            previous_expected = None;
+            None
+        }
+    })
+}
+
+/// Serializes durations as whole milliseconds
+pub mod millis {
+    use std::time::Duration;
+
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u64(duration.as_millis() as u64)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+        u64::deserialize(deserializer).map(Duration::from_millis)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{config::ErrorMode, state::State};
+
+    fn stop_mode_session(keys: &str) -> State {
+        let mut state = State::new("the".to_string());
+        state.error_mode = ErrorMode::Stop;
+        for c in keys.chars() {
+            state.apply_char(c);
+        }
+        state
+    }
+
+    #[test]
+    fn blocked_strokes_count_as_misses_of_the_expected_key() {
+        let state = stop_mode_session("thxxe");
+        let keys = key_stats(state.keystrokes());
+
+        assert_eq!(keys[&'e'].presses, 3);
+        assert_eq!(keys[&'e'].misses, 2);
+        assert_eq!(keys[&'h'].presses, 1);
+        assert!(!keys.contains_key(&'x'));
+    }
+
+    #[test]
+    fn latency_is_averaged_over_presses_with_a_latency() {
+        let mut stats = KeyStats::default();
+        stats.add(true, None);
+        assert_eq!(stats.average_latency(), Duration::ZERO);
+
+        stats.add(true, Some(Duration::from_millis(100)));
+        stats.add(true, Some(Duration::from_millis(300)));
+        assert_eq!(stats.average_latency(), Duration::from_millis(200));
+    }
+
+    #[test]
+    fn blocked_strokes_make_no_bigrams() {
+        let state = stop_mode_session("thxxe");
+        let bigrams = bigram_stats(state.keystrokes());
+
+        assert_eq!(bigrams.keys().collect::<Vec<_>>(), ["he", "th"]);
+        assert_eq!(bigrams["he"].presses, 1);
+        assert_eq!(bigrams["he"].misses, 0);
+    }
+}
@@ -0,0 +1,288 @@
+use std::{iter::repeat_n, path::Path};
+
+/// Class of the token a character belongs to, decides its color before it is typed
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TokenClass {
+    Plain,
+    Keyword,
+    Type,
+    Function,
+    String,
+    Number,
+    Comment,
+    Punctuation,
+}
+
+/// Token classes of every character, one line per line of the page
+pub type Annotations = Vec<Vec<TokenClass>>;
+
+/// Languages with syntax highlighting of code snippets
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Language {
+    Rust,
+    Python,
+    JavaScript,
+    Shell,
+}
+
+const RUST_KEYWORDS: &[&str] = &[
+    "as", "async", "await", "break", "const", "continue", "crate", "dyn", "else", "enum", "extern",
+    "false", "fn", "for", "if", "impl", "in", "let", "loop", "match", "mod", "move", "mut", "pub",
+    "ref", "return", "self", "Self", "static", "struct", "super", "trait", "true", "type",
+    "unsafe", "use", "where", "while",
+];
+
+const PYTHON_KEYWORDS: &[&str] = &[
+    "and", "as", "assert", "async", "await", "break", "class", "continue", "def", "del", "elif",
+    "else", "except", "False", "finally", "for", "from", "global", "if", "import", "in", "is",
+    "lambda", "None", "nonlocal", "not", "or", "pass", "raise", "return", "self", "True", "try",
+    "while", "with", "yield",
+];
+
+const JAVASCRIPT_KEYWORDS: &[&str] = &[
+    "async", "await", "break", "case", "catch", "class", "const", "continue", "debugger",
+    "default", "delete", "do", "else", "export", "extends", "false", "finally", "for", "function",
+    "if", "import", "in", "instanceof", "let", "new", "null", "of", "return", "static", "super",
+    "switch", "this", "throw", "true", "try", "typeof", "undefined", "var", "void", "while",
+    "yield",
+];
+
+const SHELL_KEYWORDS: &[&str] = &[
+    "case", "do", "done", "elif", "else", "esac", "exit", "export", "fi", "for", "function", "if",
+    "in", "local", "readonly", "return", "set", "shift", "then", "unset", "until", "while",
+];
+
+impl Language {
+    /// Language of a source file, judging by its extension
+    pub fn from_path(path: &str) -> Option<Self> {
+        let extension = Path::new(path).extension()?.to_str()?;
+
+        match extension {
+            "rs" => Some(Language::Rust),
+            "py" => Some(Language::Python),
+            "js" | "mjs" | "cjs" | "jsx" | "ts" | "tsx" => Some(Language::JavaScript),
+            "sh" | "bash" | "zsh" => Some(Language::Shell),
+            _ => None,
+        }
+    }
+
+    fn keywords(self) -> &'static [&'static str] {
+        match self {
+            Language::Rust => RUST_KEYWORDS,
+            Language::Python => PYTHON_KEYWORDS,
+            Language::JavaScript => JAVASCRIPT_KEYWORDS,
+            Language::Shell => SHELL_KEYWORDS,
+        }
+    }
+
+    fn line_comment(self) -> &'static str {
+        match self {
+            Language::Rust | Language::JavaScript => "//",
+            Language::Python | Language::Shell => "#",
+        }
+    }
+
+    fn has_block_comments(self) -> bool {
+        matches!(self, Language::Rust | Language::JavaScript)
+    }
+
+    fn quotes(self) -> &'static [char] {
+        match self {
+            Language::JavaScript => &['"', '\'', '`'],
+            _ => &['"', '\''],
+        }
+    }
+}
+
+/// Splits the text into lines of token classes, aligned with the characters of the page
+pub fn annotate(text: &str, language: Language) -> Annotations {
+    let chars: Vec<_> = text.chars().collect();
+    let classes = tokenize(&chars, language);
+
+    chars
+        .split(|&c| c == '\n')
+        .scan(0, |start, line| {
+            let classes = classes[*start..*start + line.len()].to_vec();
+            *start += line.len() + 1;
+            Some(classes)
+        })
+        .collect()
+}
+
+fn tokenize(chars: &[char], language: Language) -> Vec<TokenClass> {
+    let mut classes = Vec::with_capacity(chars.len());
+
+    while classes.len() < chars.len() {
+        let position = classes.len();
+        let previous = position.checked_sub(1).map(|index| chars[index]);
+        let (class, len) = next_token(&chars[position..], previous, language);
+        classes.extend(repeat_n(class, len));
+    }
+
+    classes
+}
+
+/// Class and length of the token at the start of `rest`
+fn next_token(rest: &[char], previous: Option<char>, language: Language) -> (TokenClass, usize) {
+    let c = rest[0];
+    // in shell a `#` inside a word, e.g. `${#array}`, does not start a comment
+    let word_start = previous.is_none_or(|p| p.is_whitespace());
+
+    if starts_with(rest, language.line_comment()) && (language != Language::Shell || word_start) {
+        let len = rest.iter().position(|&c| c == '\n').unwrap_or(rest.len());
+        return (TokenClass::Comment, len);
+    }
+
+    if language.has_block_comments() && starts_with(rest, "/*") {
+        let len = find(&rest[2..], "*/").map_or(rest.len(), |end| end + 4);
+        return (TokenClass::Comment, len);
+    }
+
+    if language.quotes().contains(&c)
+        && let Some(len) = string_len(rest, language)
+    {
+        return (TokenClass::String, len);
+    }
+
+    if c.is_ascii_digit() {
+        let len = rest
+            .iter()
+            .take_while(|&&c| c.is_ascii_alphanumeric() || c == '_' || c == '.')
+            .count();
+        return (TokenClass::Number, len);
+    }
+
+    if c.is_alphabetic() || c == '_' {
+        let word: String = rest
+            .iter()
+            .take_while(|&&c| c.is_alphanumeric() || c == '_')
+            .collect();
+        let len = word.chars().count();
+        let next = rest.get(len).copied();
+
+        let class = if language.keywords().contains(&word.as_str()) {
+            TokenClass::Keyword
+        } else if next == Some('(') || (language == Language::Rust && next == Some('!')) {
+            TokenClass::Function
+        } else if c.is_uppercase() && language != Language::Shell {
+            TokenClass::Type
+        } else {
+            TokenClass::Plain
+        };
+        return (class, len);
+    }
+
+    if c.is_ascii_punctuation() {
+        return (TokenClass::Punctuation, 1);
+    }
+
+    (TokenClass::Plain, 1)
+}
+
+/// Length of the string literal at the start of `rest`, `None` when the quote does not start one
+fn string_len(rest: &[char], language: Language) -> Option<usize> {
+    let quote = rest[0];
+
+    if language == Language::Python {
+        let triple = [quote; 3];
+        if rest.starts_with(&triple) {
+            let end = rest[3..].windows(3).position(|window| window == triple);
+            return Some(end.map_or(rest.len(), |end| end + 6));
+        }
+    }
+
+    // Rust lifetimes like 'a are not closed, char literals are at most 4 characters like '\n'
+    let char_literal = language == Language::Rust && quote == '\'';
+    let multiline = quote == '`' || (language == Language::Rust && quote == '"');
+
+    let mut escaped = false;
+    for (index, &c) in rest.iter().enumerate().skip(1) {
+        if char_literal && index > 3 {
+            return None;
+        }
+
+        if escaped {
+            escaped = false;
+        } else if c == '\\' && !(language == Language::Shell && quote == '\'') {
+            escaped = true;
+        } else if c == quote {
+            return Some(index + 1);
+        } else if c == '\n' && !multiline {
+            return (!char_literal).then_some(index);
+        }
+    }
+
+    (!char_literal).then_some(rest.len())
+}
+
+fn starts_with(chars: &[char], prefix: &str) -> bool {
+    prefix.chars().enumerate().all(|(index, c)| chars.get(index) == Some(&c))
+}
+
+fn find(chars: &[char], pattern: &str) -> Option<usize> {
+    (0..chars.len()).find(|&index| starts_with(&chars[index..], pattern))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// One letter per character: plain `.`, keyword `k`, type `t`, function `f`, string `s`,
+    /// number `n`, comment `c` and punctuation `p`
+    fn classes(text: &str, language: Language) -> Vec<String> {
+        let letter = |class: &TokenClass| match class {
+            TokenClass::Plain => '.',
+            TokenClass::Keyword => 'k',
+            TokenClass::Type => 't',
+            TokenClass::Function => 'f',
+            TokenClass::String => 's',
+            TokenClass::Number => 'n',
+            TokenClass::Comment => 'c',
+            TokenClass::Punctuation => 'p',
+        };
+
+        annotate(text, language)
+            .iter()
+            .map(|line| line.iter().map(letter).collect())
+            .collect()
+    }
+
+    #[test]
+    fn escaped_quotes_do_not_end_strings() {
+        assert_eq!(classes(r#"a = "x\"y";"#, Language::Rust), ["..p.ssssssp"]);
+        assert_eq!(classes(r#""a\\" b"#, Language::JavaScript), ["sssss.."]);
+        // single quotes in shell take backslashes literally
+        assert_eq!(classes(r"'a\' b", Language::Shell), ["ssss.."]);
+    }
+
+    #[test]
+    fn unterminated_strings_end_at_the_line_end() {
+        assert_eq!(classes("x = \"abc\ny = 1", Language::Python), ["..p.ssss", "..p.n"]);
+        assert_eq!(classes("'abc\nx", Language::JavaScript), ["ssss", "."]);
+    }
+
+    #[test]
+    fn rust_strings_span_lines_and_lifetimes_are_not_strings() {
+        assert_eq!(classes("\"abc\nx\"", Language::Rust), ["ssss", "ss"]);
+        assert_eq!(classes("&'a str", Language::Rust), ["pp....."]);
+        assert_eq!(classes("'\\n'", Language::Rust), ["ssss"]);
+    }
+
+    #[test]
+    fn line_comments_end_at_the_line_end() {
+        assert_eq!(classes("a // b /* c\nd", Language::Rust), ["..ccccccccc", "."]);
+        assert_eq!(classes("x # y\nz", Language::Python), ["..ccc", "."]);
+    }
+
+    #[test]
+    fn block_comments_span_lines() {
+        assert_eq!(classes("d /* e\nf */ g", Language::Rust), ["..cccc", "cccc.."]);
+        assert_eq!(classes("/* open", Language::JavaScript), ["ccccccc"]);
+        assert_eq!(classes("a /* b", Language::Python), ["..pp.."]);
+    }
+
+    #[test]
+    fn shell_comments_start_words() {
+        assert_eq!(classes("echo ${#a} # b", Language::Shell), [".....ppp.p.ccc"]);
+    }
+}
@@ -1,11 +1,20 @@
-use core::str::FromStr;
+use core::{fmt, str::FromStr};
 
 use color_eyre::Result;
-use rand::{seq::IndexedRandom, RngExt};
+use rand::{Rng, RngExt, seq::IndexedRandom};
 
-use crate::{config::Config, markov::MarkovChain};
+use crate::{
+    adaptive::Weakness,
+    code::pick_snippet,
+    config::Config,
+    corpus::Filters,
+    decorate::{Decorations, decorate},
+    history::History,
+    markov::{MarkovChain, MarkovSource, Sampling},
+    syntax::Language,
+};
 
-const TEXT: &'static str =
+const TEXT: &str =
     "This is a bare minimum example.   There are many approaches to running an application loop, so
 this is not meant to be prescriptive. It is only meant to demonstrate the basic setup and
 teardown of a terminal application.";
@@ -21,11 +30,32 @@ pub enum TextSource {
     /// Generate nonsense from a list of weighted words
     GenerateWeightedNonsense,
 
+    /// Generate nonsense from a list of weighted words, favoring the weakest letters and bigrams
+    Adaptive,
+
     /// Grab lines from a file
     File(String),
 
     /// Generate text using Markov Chain
-    MarkovChain(String),
+    MarkovChain(MarkovSource),
+
+    /// Function sized snippet from a source file, typed with exact indentation
+    Code(String),
+}
+
+impl TextSource {
+    /// Whether the text is code, whose whitespace must be kept as is
+    pub fn is_code(&self) -> bool {
+        matches!(self, TextSource::Code(_))
+    }
+
+    /// Language of code sources with syntax highlighting
+    pub fn language(&self) -> Option<Language> {
+        match self {
+            TextSource::Code(path) => Language::from_path(path),
+            _ => None,
+        }
+    }
 }
 
 impl FromStr for TextSource {
@@ -36,6 +66,7 @@ impl FromStr for TextSource {
             "static" => Ok(TextSource::Static),
             "nonsense" => Ok(TextSource::GenerateNonsense),
             "weighted" => Ok(TextSource::GenerateWeightedNonsense),
+            "adaptive" => Ok(TextSource::Adaptive),
             _ => {
                 if let Some(path) = source.strip_prefix("file:") {
                     if path.is_empty() {
@@ -43,41 +74,246 @@ impl FromStr for TextSource {
                     } else {
                         Ok(TextSource::File(path.to_string()))
                     }
-                } else if let Some(path) = source.strip_prefix("markov:") {
+                } else if source.starts_with("markov:") {
+                    source.parse().map(TextSource::MarkovChain)
+                } else if let Some(path) = source.strip_prefix("code:") {
                     if path.is_empty() {
-                        Err("markov source expects file path, .e.g markov:data/markov.txt".to_string())
+                        Err("code source expects file path, .e.g code:src/main.rs".to_string())
                     } else {
-                        Ok(TextSource::MarkovChain(path.to_string()))
+                        Ok(TextSource::Code(path.to_string()))
                     }
                 } else {
-                    Err("expected static, nonsense, weighted, file:<path> or markov:<path>".to_string())
+                    Err("expected static, nonsense, weighted, adaptive, file:<path>, markov:<path> or code:<path>".to_string())
+                }
+            }
+        }
+    }
+}
+
+impl fmt::Display for TextSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TextSource::Static => write!(f, "static"),
+            TextSource::GenerateNonsense => write!(f, "nonsense"),
+            TextSource::GenerateWeightedNonsense => write!(f, "weighted"),
+            TextSource::Adaptive => write!(f, "adaptive"),
+            TextSource::File(path) => write!(f, "file:{path}"),
+            TextSource::MarkovChain(markov) => write!(f, "{markov}"),
+            TextSource::Code(path) => write!(f, "code:{path}"),
+        }
+    }
+}
+
+/// What a text source draws its text from, read from disk once
+#[derive(Debug)]
+enum Material {
+    Static,
+    Words(Vec<String>),
+    WeightedWords(Vec<(String, u32)>),
+    /// Weighted words boosted by the weakness found in the history
+    Adaptive(Vec<(String, u32)>, Weakness),
+    Lines(Vec<String>),
+    Chain(MarkovChain),
+    Code { path: String, contents: String },
+}
+
+/// Generates text of a source, files, models and history are loaded when it is created so
+/// timed sessions can draw more text without touching the disk
+#[derive(Debug)]
+pub struct Generator {
+    material: Material,
+    max_words: usize,
+    decorations: Decorations,
+    sampling: Sampling,
+
+    /// Line width generated text is reflowed to, none keeps its line breaks
+    reflow: Option<Width>,
+}
+
+impl Generator {
+    pub fn new(
+        source: &TextSource,
+        max_words: usize,
+        decorations: Decorations,
+        sampling: Sampling,
+        filters: &Filters,
+    ) -> Result<Self> {
+        let material = match source {
+            TextSource::Static => Material::Static,
+            TextSource::GenerateNonsense => {
+                let contents = std::fs::read_to_string("data/words.txt")?;
+                Material::Words(contents.lines().map(String::from).collect())
+            }
+            TextSource::GenerateWeightedNonsense => Material::WeightedWords(read_weighted_words()?),
+            TextSource::Adaptive => {
+                let weakness = Weakness::from_history(&History::load()?);
+                if weakness.is_empty() {
+                    // nothing is known about the user yet
+                    Material::WeightedWords(read_weighted_words()?)
+                } else {
+                    Material::Adaptive(read_weighted_words()?, weakness)
                 }
             }
+            TextSource::File(path) => {
+                let contents = filters.apply(&std::fs::read_to_string(path)?);
+                Material::Lines(contents.lines().map(String::from).collect())
+            }
+            TextSource::MarkovChain(markov) => Material::Chain(MarkovChain::load(markov, filters)?),
+            TextSource::Code(path) => Material::Code {
+                path: path.clone(),
+                contents: std::fs::read_to_string(path)?,
+            },
+        };
+
+        Ok(Self {
+            material,
+            max_words,
+            decorations,
+            sampling,
+            reflow: None,
+        })
+    }
+
+    pub fn from_config(config: &Config) -> Result<Self> {
+        let mut generator = Self::new(
+            &config.text_source,
+            config.max_words,
+            Decorations::from_config(config),
+            Sampling::from_config(config),
+            &Filters::from_config(config),
+        )?;
+        // reflowing would collapse the indentation of code
+        if config.reflow && !config.text_source.is_code() {
+            generator.reflow = Some(config.width);
+        }
+        Ok(generator)
+    }
+
+    pub fn generate(&self, rng: &mut impl Rng) -> Result<String> {
+        let text = match &self.material {
+            Material::Static => TEXT.to_string(),
+            Material::Words(words) => {
+                let selected_words: Vec<_> = words
+                    .sample(rng, self.max_words)
+                    .map(String::as_str)
+                    .collect();
+                self.decorate(&selected_words, rng)
+            }
+            Material::WeightedWords(words) => {
+                let selected_words: Vec<_> = words
+                    .sample_weighted(rng, self.max_words, |item| item.1)?
+                    .map(|item| item.0.as_str())
+                    .collect();
+                self.decorate(&selected_words, rng)
+            }
+            Material::Adaptive(words, weakness) => {
+                let selected_words: Vec<_> = words
+                    .sample_weighted(rng, self.max_words, |item| {
+                        item.1 as f64 * weakness.boost(&item.0)
+                    })?
+                    .map(|item| item.0.as_str())
+                    .collect();
+                self.decorate(&selected_words, rng)
+            }
+            Material::Lines(lines) => select_lines(lines, rng),
+            Material::Chain(chain) => chain.generate(self.max_words, &self.sampling, rng)?,
+            Material::Code { path, contents } => pick_snippet(path, contents, rng)?,
+        };
+
+        Ok(match self.reflow {
+            Some(width) => reflow(&text, width.resolve()),
+            None => text,
+        })
+    }
+
+    /// Decorates the words and lays them out ten per line
+    fn decorate(&self, words: &[&str], rng: &mut impl Rng) -> String {
+        let words = decorate(words, &self.decorations, rng);
+        let lines: Vec<_> = words.chunks(10).map(|chunk| chunk.join(" ")).collect();
+        lines.join("\n")
+    }
+}
+
+pub fn generate_text(
+    source: &TextSource,
+    max_words: usize,
+    decorations: &Decorations,
+    sampling: &Sampling,
+    filters: &Filters,
+    rng: &mut impl Rng,
+) -> Result<String> {
+    Generator::new(source, max_words, decorations.clone(), sampling.clone(), filters)?.generate(rng)
+}
+
+/// Line width used for reflowing text
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Width {
+    /// Fixed number of characters per line
+    Fixed(usize),
+
+    /// Follow the width of the terminal
+    Auto,
+}
+
+/// Columns left free on each side of the text in auto width mode
+const AUTO_WIDTH_MARGIN: usize = 2;
+
+impl Width {
+    pub fn resolve(&self) -> usize {
+        match self {
+            Width::Fixed(width) => *width,
+            Width::Auto => {
+                let columns = crossterm::terminal::size().map_or(80, |(columns, _)| columns as usize);
+                Self::for_columns(columns)
+            }
         }
     }
+
+    /// Usable line width for a terminal that many columns wide
+    pub fn for_columns(columns: usize) -> usize {
+        columns.saturating_sub(2 * AUTO_WIDTH_MARGIN).max(1)
+    }
 }
 
-pub fn get_text(config: &Config) -> Result<String> {
-    let text = match config.text_source {
-        TextSource::Static => Ok(TEXT.to_string()),
-        TextSource::GenerateNonsense => generate_nonsense(config.max_words),
-        TextSource::GenerateWeightedNonsense => generate_weighted_nonsense(config.max_words),
-        TextSource::File(ref path) => read_lines_from_file(path),
-        TextSource::MarkovChain(ref path) => generate_markov_chain(path, config.max_words),
-    }?;
+impl FromStr for Width {
+    type Err = String;
 
-    Ok(if config.reflow { reflow(&text, config.width) } else { text })
+    fn from_str(width: &str) -> core::result::Result<Self, Self::Err> {
+        if width == "auto" {
+            return Ok(Width::Auto);
+        }
+
+        match width.parse::<usize>() {
+            Ok(width) if width > 0 => Ok(Width::Fixed(width)),
+            _ => Err("expected a positive number of characters or auto".to_string()),
+        }
+    }
+}
+
+impl fmt::Display for Width {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Width::Fixed(width) => write!(f, "{width}"),
+            Width::Auto => write!(f, "auto"),
+        }
+    }
 }
 
 fn reflow(text: &str, width: usize) -> String {
+    reflow_continued(text, width, 0)
+}
+
+/// Reflows text as a continuation of a line already `column` characters wide,
+/// the first word is then preceded by either a space or a line break
+pub fn reflow_continued(text: &str, width: usize, column: usize) -> String {
     let mut lines = Vec::new();
     let mut line = String::new();
-    let mut line_width = 0;
+    let mut line_width = column;
 
     for word in text.split_whitespace() {
         let word_width = word.chars().count();
 
-        if line.is_empty() {
+        if line_width == 0 {
             line.push_str(word);
             line_width = word_width;
         } else if line_width + 1 + word_width <= width {
@@ -98,56 +334,31 @@ fn reflow(text: &str, width: usize) -> String {
     lines.join("\n")
 }
 
-fn generate_nonsense(max_words: usize) -> Result<String> {
-    let contents = std::fs::read_to_string("data/words.txt")?;
-    let words: Vec<_> = contents.lines().collect();
-
-    let mut rng = rand::rng();
-    let selected_words: Vec<_> = words.sample(&mut rng, max_words).copied().collect();
-    let lines: Vec<_> = selected_words
-        .chunks(10)
-        .map(|chunk| chunk.join(" "))
-        .collect();
-
-    Ok(lines.join("\n"))
+fn read_weighted_words() -> Result<Vec<(String, u32)>> {
+    let contents = std::fs::read_to_string("data/words_weighted.txt")?;
+    parse_weighted_words(&contents)
 }
 
-fn generate_weighted_nonsense(max_words: usize) -> Result<String> {
-    let contents = std::fs::read_to_string("data/words_weighted.txt")?;
+fn parse_weighted_words(contents: &str) -> Result<Vec<(String, u32)>> {
     let mut words = Vec::new();
 
     for line in contents.lines() {
         let parts: Vec<_> = line.split(" ").collect();
         assert_eq!(parts.len(), 2);
         let weight = parts[1].parse::<u32>()?;
-        words.push((parts[0], weight));
+        words.push((parts[0].to_string(), weight));
     }
 
-    let mut rng = rand::rng();
-    let selected_words: Vec<_> = words
-        .sample_weighted(&mut rng, max_words, |item| item.1)?
-        .map(|item| item.0)
-        .collect();
-
-    let lines: Vec<_> = selected_words
-        .chunks(10)
-        .map(|chunk| chunk.join(" "))
-        .collect();
-
-    Ok(lines.join("\n"))
+    Ok(words)
 }
 
-fn read_lines_from_file(path: &str) -> Result<String> {
-    let contents = std::fs::read_to_string(path)?;
-    let lines: Vec<_> = contents.lines().collect();
-
+fn select_lines(lines: &[String], rng: &mut impl Rng) -> String {
     if lines.is_empty() {
-        return Ok("".to_string());
+        return "".to_string();
     }
 
     let max_lines = lines.len().min(5);
     let max_start_index = lines.len() - max_lines;
-    let mut rng = rand::rng();
     let start_index = if max_start_index > 0 {
         rng.random_range(0..=max_start_index)
     } else {
@@ -155,11 +366,65 @@ fn read_lines_from_file(path: &str) -> Result<String> {
     };
 
     let selected_lines = &lines[start_index..start_index + max_lines];
-    Ok(selected_lines.join("\n"))
+    // the excerpt may start or end at a paragraph break
+    selected_lines.join("\n").trim_matches('\n').to_string()
 }
 
-fn generate_markov_chain(path: &str, max_words: usize) -> Result<String> {
-    let contents = std::fs::read_to_string(path)?;
-    let chain = MarkovChain::build(&contents);
-    Ok(chain.generate(max_words))
+#[cfg(test)]
+mod tests {
+    use rand::{SeedableRng, rngs::StdRng};
+
+    use super::*;
+
+    fn generate(source: &str, seed: u64) -> String {
+        let mut rng = StdRng::seed_from_u64(seed);
+        generate_text(
+            &source.parse().unwrap(),
+            8,
+            &Decorations::default(),
+            &Sampling::default(),
+            &Filters::default(),
+            &mut rng,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn static_text_ignores_the_seed() {
+        assert_eq!(generate("static", 1), TEXT);
+        assert_eq!(generate("static", 2), TEXT);
+    }
+
+    #[test]
+    fn nonsense_is_pinned_by_the_seed() {
+        assert_eq!(generate("nonsense", 42), "say matter few energy shoulder tall range fall");
+        assert_eq!(generate("nonsense", 42), generate("nonsense", 42));
+        assert_ne!(generate("nonsense", 42), generate("nonsense", 43));
+    }
+
+    #[test]
+    fn weighted_nonsense_is_pinned_by_the_seed() {
+        assert_eq!(generate("weighted", 42), "trying back son Sid call them night checks");
+    }
+
+    #[test]
+    fn file_excerpt_is_pinned_by_the_seed() {
+        assert_eq!(
+            generate("file:data/markov.txt", 42),
+            "Mercia and Northumbria-'\"\n\n\"Ugh!\" said the Lory, with a shiver.\n\n\
+             \"I beg your pardon!\" said the Mouse, frowning, but very politely: \"Did"
+        );
+    }
+
+    #[test]
+    fn reflow_breaks_lines_at_the_width() {
+        assert_eq!(reflow("a bb ccc\ndd", 5), "a bb\nccc\ndd");
+        assert_eq!(reflow("abcdefgh x", 3), "abcdefgh\nx");
+    }
+
+    #[test]
+    fn reflow_continues_a_partly_filled_line() {
+        assert_eq!(reflow_continued("dd ee", 6, 3), " dd\nee");
+        assert_eq!(reflow_continued("dd", 4, 4), "\ndd");
+    }
 }
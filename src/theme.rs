@@ -0,0 +1,377 @@
+use core::str::FromStr;
+use std::{fs, path::PathBuf};
+
+use color_eyre::{Result, eyre::eyre};
+use ratatui::style::{Color, Modifier, Style};
+use serde::{Deserialize, Deserializer, de::Error};
+
+use crate::{config::Config, syntax::TokenClass};
+
+/// Names of the themes shipped with TypeGym
+pub const BUILT_IN: [&str; 4] = ["default", "gruvbox", "nord", "solarized-light"];
+
+#[derive(Debug, Clone)]
+pub struct Theme {
+    /// Correctly typed character
+    pub hit: Style,
+
+    /// Incorrectly typed character
+    pub miss: Style,
+
+    /// Untyped character
+    pub empty: Style,
+
+    /// Character under the cursor
+    pub cursor: Style,
+
+    /// Ghost cursor when racing a previous run
+    pub ghost: Style,
+
+    /// Results shown after the session
+    pub results: Style,
+
+    /// Live status bar and hints
+    pub status: Style,
+
+    /// The whole page behind the text
+    pub background: Style,
+
+    /// Untyped code keyword, patched over the style of untyped characters
+    pub keyword: Style,
+
+    /// Untyped code type name
+    pub type_name: Style,
+
+    /// Untyped code function or macro name
+    pub function: Style,
+
+    /// Untyped code string literal
+    pub string: Style,
+
+    /// Untyped code number literal
+    pub number: Style,
+
+    /// Untyped code comment
+    pub comment: Style,
+
+    /// Untyped code punctuation and operators
+    pub punctuation: Style,
+
+    /// Heatmap key with few misses or faster than average
+    pub heat_cold: Style,
+
+    /// Heatmap key with some misses or about average speed
+    pub heat_warm: Style,
+
+    /// Heatmap key with many misses or slower than average
+    pub heat_hot: Style,
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Self {
+            hit: Style::default(),
+            miss: Style::default()
+                .fg(Color::Indexed(1))
+                .add_modifier(Modifier::BOLD),
+            empty: Style::default().fg(Color::Indexed(8)),
+            cursor: Style::default(),
+            ghost: Style::default().add_modifier(Modifier::REVERSED),
+            results: Style::default()
+                .fg(Color::Indexed(1))
+                .add_modifier(Modifier::BOLD),
+            status: Style::default().fg(Color::Indexed(8)),
+            background: Style::default(),
+            keyword: Style::default().fg(Color::Indexed(5)),
+            type_name: Style::default().fg(Color::Indexed(3)),
+            function: Style::default().fg(Color::Indexed(4)),
+            string: Style::default().fg(Color::Indexed(2)),
+            number: Style::default().fg(Color::Indexed(6)),
+            comment: Style::default().add_modifier(Modifier::ITALIC),
+            punctuation: Style::default(),
+            heat_cold: Style::default().fg(Color::Black).bg(Color::Indexed(2)),
+            heat_warm: Style::default().fg(Color::Black).bg(Color::Indexed(3)),
+            heat_hot: Style::default().fg(Color::Black).bg(Color::Indexed(1)),
+        }
+    }
+}
+
+impl Theme {
+    /// Loads the configured theme and applies ANSI color overrides on top of it
+    pub fn from_config(config: &Config) -> Result<Self> {
+        let mut theme = Self::load(&config.theme)?;
+
+        if let Some(color) = config.fg_miss {
+            theme.miss = theme.miss.fg(Color::Indexed(color));
+        }
+        if let Some(color) = config.fg_empty {
+            theme.empty = theme.empty.fg(Color::Indexed(color));
+        }
+        if let Some(color) = config.fg_results {
+            theme.results = theme.results.fg(Color::Indexed(color));
+        }
+
+        Ok(theme)
+    }
+
+    /// Style of an untyped code token, patched over `empty`
+    pub fn token(&self, class: TokenClass) -> Style {
+        match class {
+            TokenClass::Plain => Style::default(),
+            TokenClass::Keyword => self.keyword,
+            TokenClass::Type => self.type_name,
+            TokenClass::Function => self.function,
+            TokenClass::String => self.string,
+            TokenClass::Number => self.number,
+            TokenClass::Comment => self.comment,
+            TokenClass::Punctuation => self.punctuation,
+        }
+    }
+
+    /// Loads a built-in theme, a theme from ~/.config/typegym/themes/<name>.toml or a theme file path
+    pub fn load(name: &str) -> Result<Self> {
+        if let Some(theme) = Self::built_in(name) {
+            return Ok(theme);
+        }
+
+        let path = if name.ends_with(".toml") {
+            PathBuf::from(name)
+        } else {
+            let config_dir =
+                dirs::config_dir().ok_or_else(|| eyre!("could not determine config directory"))?;
+            config_dir
+                .join("typegym")
+                .join("themes")
+                .join(format!("{name}.toml"))
+        };
+
+        if !path.exists() {
+            return Err(eyre!(
+                "unknown theme {name}, expected one of {} or a theme file at {}",
+                BUILT_IN.join(", "),
+                path.display()
+            ));
+        }
+
+        let contents = fs::read_to_string(&path)
+            .map_err(|e| eyre!("could not read theme file {}: {e}", path.display()))?;
+        let file: ThemeFile = toml::from_str(&contents)
+            .map_err(|e| eyre!("invalid theme file {}:\n{e}", path.display()))?;
+
+        let base = match &file.extends {
+            Some(base) => Self::built_in(base).ok_or_else(|| {
+                eyre!(
+                    "theme {} extends unknown theme {base}, expected one of {}",
+                    path.display(),
+                    BUILT_IN.join(", ")
+                )
+            })?,
+            None => Self::default(),
+        };
+
+        Ok(file.apply(base))
+    }
+
+    fn built_in(name: &str) -> Option<Self> {
+        let theme = match name {
+            "default" => Self::default(),
+            "gruvbox" => Self {
+                hit: Style::default().fg(Color::Rgb(0xeb, 0xdb, 0xb2)),
+                miss: Style::default()
+                    .fg(Color::Rgb(0xfb, 0x49, 0x34))
+                    .add_modifier(Modifier::BOLD | Modifier::UNDERLINED),
+                empty: Style::default().fg(Color::Rgb(0x66, 0x5c, 0x54)),
+                cursor: Style::default().bg(Color::Rgb(0x50, 0x49, 0x45)),
+                ghost: Style::default().bg(Color::Rgb(0x45, 0x85, 0x88)),
+                results: Style::default()
+                    .fg(Color::Rgb(0xfa, 0xbd, 0x2f))
+                    .add_modifier(Modifier::BOLD),
+                status: Style::default().fg(Color::Rgb(0x92, 0x83, 0x74)),
+                background: Style::default().bg(Color::Rgb(0x28, 0x28, 0x28)),
+                keyword: Style::default().fg(Color::Rgb(0xfb, 0x49, 0x34)),
+                type_name: Style::default().fg(Color::Rgb(0xfa, 0xbd, 0x2f)),
+                function: Style::default().fg(Color::Rgb(0xb8, 0xbb, 0x26)),
+                string: Style::default().fg(Color::Rgb(0x8e, 0xc0, 0x7c)),
+                number: Style::default().fg(Color::Rgb(0xd3, 0x86, 0x9b)),
+                comment: Style::default()
+                    .fg(Color::Rgb(0x7c, 0x6f, 0x64))
+                    .add_modifier(Modifier::ITALIC),
+                punctuation: Style::default(),
+                heat_cold: Style::default().fg(Color::Rgb(0x28, 0x28, 0x28)).bg(Color::Rgb(0xb8, 0xbb, 0x26)),
+                heat_warm: Style::default().fg(Color::Rgb(0x28, 0x28, 0x28)).bg(Color::Rgb(0xfa, 0xbd, 0x2f)),
+                heat_hot: Style::default().fg(Color::Rgb(0x28, 0x28, 0x28)).bg(Color::Rgb(0xfb, 0x49, 0x34)),
+            },
+            "nord" => Self {
+                hit: Style::default().fg(Color::Rgb(0xec, 0xef, 0xf4)),
+                miss: Style::default()
+                    .fg(Color::Rgb(0xbf, 0x61, 0x6a))
+                    .add_modifier(Modifier::BOLD),
+                empty: Style::default().fg(Color::Rgb(0x4c, 0x56, 0x6a)),
+                cursor: Style::default().bg(Color::Rgb(0x43, 0x4c, 0x5e)),
+                ghost: Style::default().bg(Color::Rgb(0x5e, 0x81, 0xac)),
+                results: Style::default()
+                    .fg(Color::Rgb(0x88, 0xc0, 0xd0))
+                    .add_modifier(Modifier::BOLD),
+                status: Style::default().fg(Color::Rgb(0x81, 0xa1, 0xc1)),
+                background: Style::default().bg(Color::Rgb(0x2e, 0x34, 0x40)),
+                keyword: Style::default().fg(Color::Rgb(0x81, 0xa1, 0xc1)),
+                type_name: Style::default().fg(Color::Rgb(0x8f, 0xbc, 0xbb)),
+                function: Style::default().fg(Color::Rgb(0x88, 0xc0, 0xd0)),
+                string: Style::default().fg(Color::Rgb(0xa3, 0xbe, 0x8c)),
+                number: Style::default().fg(Color::Rgb(0xb4, 0x8e, 0xad)),
+                comment: Style::default()
+                    .fg(Color::Rgb(0x61, 0x6e, 0x88))
+                    .add_modifier(Modifier::ITALIC),
+                punctuation: Style::default(),
+                heat_cold: Style::default().fg(Color::Rgb(0x2e, 0x34, 0x40)).bg(Color::Rgb(0xa3, 0xbe, 0x8c)),
+                heat_warm: Style::default().fg(Color::Rgb(0x2e, 0x34, 0x40)).bg(Color::Rgb(0xeb, 0xcb, 0x8b)),
+                heat_hot: Style::default().fg(Color::Rgb(0x2e, 0x34, 0x40)).bg(Color::Rgb(0xbf, 0x61, 0x6a)),
+            },
+            "solarized-light" => Self {
+                hit: Style::default().fg(Color::Rgb(0x07, 0x36, 0x42)),
+                miss: Style::default()
+                    .fg(Color::Rgb(0xdc, 0x32, 0x2f))
+                    .add_modifier(Modifier::BOLD),
+                empty: Style::default().fg(Color::Rgb(0x93, 0xa1, 0xa1)),
+                cursor: Style::default().bg(Color::Rgb(0xee, 0xe8, 0xd5)),
+                ghost: Style::default().bg(Color::Rgb(0xb5, 0x89, 0x00)),
+                results: Style::default()
+                    .fg(Color::Rgb(0x26, 0x8b, 0xd2))
+                    .add_modifier(Modifier::BOLD),
+                status: Style::default().fg(Color::Rgb(0x58, 0x6e, 0x75)),
+                background: Style::default().bg(Color::Rgb(0xfd, 0xf6, 0xe3)),
+                keyword: Style::default().fg(Color::Rgb(0x85, 0x99, 0x00)),
+                type_name: Style::default().fg(Color::Rgb(0xb5, 0x89, 0x00)),
+                function: Style::default().fg(Color::Rgb(0x26, 0x8b, 0xd2)),
+                string: Style::default().fg(Color::Rgb(0x2a, 0xa1, 0x98)),
+                number: Style::default().fg(Color::Rgb(0xd3, 0x36, 0x82)),
+                comment: Style::default()
+                    .fg(Color::Rgb(0x93, 0xa1, 0xa1))
+                    .add_modifier(Modifier::ITALIC),
+                punctuation: Style::default(),
+                heat_cold: Style::default().fg(Color::Rgb(0xfd, 0xf6, 0xe3)).bg(Color::Rgb(0x85, 0x99, 0x00)),
+                heat_warm: Style::default().fg(Color::Rgb(0xfd, 0xf6, 0xe3)).bg(Color::Rgb(0xb5, 0x89, 0x00)),
+                heat_hot: Style::default().fg(Color::Rgb(0xfd, 0xf6, 0xe3)).bg(Color::Rgb(0xdc, 0x32, 0x2f)),
+            },
+            _ => return None,
+        };
+
+        Some(theme)
+    }
+}
+
+/// Theme as written in a TOML file, elements missing from the file keep their base style
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ThemeFile {
+    /// Built-in theme to start from, `default` when missing
+    extends: Option<String>,
+    hit: Option<StyleFile>,
+    miss: Option<StyleFile>,
+    empty: Option<StyleFile>,
+    cursor: Option<StyleFile>,
+    ghost: Option<StyleFile>,
+    results: Option<StyleFile>,
+    status: Option<StyleFile>,
+    background: Option<StyleFile>,
+    keyword: Option<StyleFile>,
+    #[serde(rename = "type")]
+    type_name: Option<StyleFile>,
+    function: Option<StyleFile>,
+    string: Option<StyleFile>,
+    number: Option<StyleFile>,
+    comment: Option<StyleFile>,
+    punctuation: Option<StyleFile>,
+    heat_cold: Option<StyleFile>,
+    heat_warm: Option<StyleFile>,
+    heat_hot: Option<StyleFile>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct StyleFile {
+    #[serde(default, deserialize_with = "color")]
+    fg: Option<Color>,
+    #[serde(default, deserialize_with = "color")]
+    bg: Option<Color>,
+    #[serde(default, deserialize_with = "modifiers")]
+    modifiers: Modifier,
+}
+
+impl ThemeFile {
+    fn apply(self, base: Theme) -> Theme {
+        fn style(file: Option<StyleFile>, base: Style) -> Style {
+            match file {
+                Some(file) => file.into(),
+                None => base,
+            }
+        }
+
+        Theme {
+            hit: style(self.hit, base.hit),
+            miss: style(self.miss, base.miss),
+            empty: style(self.empty, base.empty),
+            cursor: style(self.cursor, base.cursor),
+            ghost: style(self.ghost, base.ghost),
+            results: style(self.results, base.results),
+            status: style(self.status, base.status),
+            background: style(self.background, base.background),
+            keyword: style(self.keyword, base.keyword),
+            type_name: style(self.type_name, base.type_name),
+            function: style(self.function, base.function),
+            string: style(self.string, base.string),
+            number: style(self.number, base.number),
+            comment: style(self.comment, base.comment),
+            punctuation: style(self.punctuation, base.punctuation),
+            heat_cold: style(self.heat_cold, base.heat_cold),
+            heat_warm: style(self.heat_warm, base.heat_warm),
+            heat_hot: style(self.heat_hot, base.heat_hot),
+        }
+    }
+}
+
+impl From<StyleFile> for Style {
+    fn from(file: StyleFile) -> Self {
+        let mut style = Style::default().add_modifier(file.modifiers);
+        if let Some(fg) = file.fg {
+            style = style.fg(fg);
+        }
+        if let Some(bg) = file.bg {
+            style = style.bg(bg);
+        }
+        style
+    }
+}
+
+fn color<'de, D: Deserializer<'de>>(deserializer: D) -> core::result::Result<Option<Color>, D::Error> {
+    let value = String::deserialize(deserializer)?;
+    Color::from_str(&value).map(Some).map_err(|_| {
+        D::Error::custom(format!(
+            "invalid color `{value}`, expected a name like red, an ANSI index like 8 or a hex value like #ff8800"
+        ))
+    })
+}
+
+fn modifiers<'de, D: Deserializer<'de>>(deserializer: D) -> core::result::Result<Modifier, D::Error> {
+    let names = Vec::<String>::deserialize(deserializer)?;
+
+    names.iter().try_fold(Modifier::empty(), |modifiers, name| {
+        let modifier = match name.as_str() {
+            "bold" => Modifier::BOLD,
+            "dim" => Modifier::DIM,
+            "italic" => Modifier::ITALIC,
+            "underlined" => Modifier::UNDERLINED,
+            "slow_blink" => Modifier::SLOW_BLINK,
+            "rapid_blink" => Modifier::RAPID_BLINK,
+            "reversed" => Modifier::REVERSED,
+            "hidden" => Modifier::HIDDEN,
+            "crossed_out" => Modifier::CROSSED_OUT,
+            _ => {
+                return Err(D::Error::custom(format!(
+                    "invalid modifier `{name}`, expected bold, dim, italic, underlined, slow_blink, rapid_blink, reversed, hidden or crossed_out"
+                )));
+            }
+        };
+        Ok(modifiers | modifier)
+    })
+}
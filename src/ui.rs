@@ -3,15 +3,25 @@ use std::{io::stdout, time::Duration};
 use color_eyre::Result;
 use crossterm::{event::{self, DisableMouseCapture, EnableMouseCapture, Event}, execute};
 
-use crate::{app::App, config::Config, state::State};
+use crate::{app::App, state::State};
 
-pub fn run_ui(state: State, config: &Config) -> Result<bool> {
+/// Restores the terminal when dropped, so errors leave the UI loop with a usable terminal
+struct RestoreGuard;
+
+impl Drop for RestoreGuard {
+    fn drop(&mut self) {
+        ratatui::restore();
+        let _ = execute!(stdout(), DisableMouseCapture);
+    }
+}
+
+pub fn run_ui(mut app: App) -> Result<State> {
     let mut terminal = ratatui::init();
+    let _guard = RestoreGuard;
     execute!(stdout(), EnableMouseCapture)?;
 
-    let mut app = App::new(state, config);
-
     loop {
+        app.tick()?;
         terminal.draw(|frame| app.draw(frame))?;
 
         if app.should_quit {
@@ -19,14 +29,13 @@ pub fn run_ui(state: State, config: &Config) -> Result<bool> {
         }
 
         if event::poll(Duration::from_millis(100))? {
-            if let Event::Key(key_code) = event::read()? {
-                app.handle_key_event(key_code.code, key_code.modifiers);
+            match event::read()? {
+                Event::Key(key_code) => app.handle_key_event(key_code.code, key_code.modifiers),
+                Event::Resize(columns, _) => app.handle_resize(columns),
+                _ => {}
             }
         }
     }
 
-    ratatui::restore();
-    execute!(stdout(), DisableMouseCapture)?;
-
-    Ok(app.state.should_loop)
+    Ok(app.state)
 }
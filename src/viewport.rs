@@ -0,0 +1,95 @@
+use std::ops::Range;
+
+/// Rows of the page visible on screen, with faded context rows around the active window
+#[derive(Debug, Clone, PartialEq)]
+pub struct Viewport {
+    /// Rows shown dimmed above the window
+    pub above: Range<usize>,
+
+    /// Rows shown normally, always containing the cursor row
+    pub window: Range<usize>,
+
+    /// Rows shown dimmed below the window
+    pub below: Range<usize>,
+}
+
+impl Viewport {
+    /// Fits a window of at most `lines` rows around the cursor into `height` rows of screen,
+    /// spending leftover height on up to `context` faded rows on each side
+    pub fn new(
+        total: usize,
+        cursor_row: usize,
+        height: usize,
+        lines: Option<usize>,
+        context: usize,
+    ) -> Self {
+        let size = total.min(lines.unwrap_or(total)).min(height);
+
+        // keep the cursor in the middle of the window while there is text left to scroll to
+        let start = cursor_row
+            .saturating_sub(size.saturating_sub(1) / 2)
+            .min(total - size);
+        let end = start + size;
+
+        let spare = (height - size) / 2;
+        let above = context.min(start).min(spare);
+        let below = context.min(total - end).min(spare);
+
+        Self {
+            above: start - above..start,
+            window: start..end,
+            below: end..end + below,
+        }
+    }
+
+    /// First row shown on screen
+    pub fn first(&self) -> usize {
+        self.above.start
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn viewport(
+        total: usize,
+        cursor_row: usize,
+        height: usize,
+        lines: Option<usize>,
+        context: usize,
+    ) -> [Range<usize>; 3] {
+        let viewport = Viewport::new(total, cursor_row, height, lines, context);
+        [viewport.above, viewport.window, viewport.below]
+    }
+
+    #[test]
+    fn cursor_on_the_first_line_has_no_context_above() {
+        assert_eq!(viewport(10, 0, 20, Some(3), 2), [0..0, 0..3, 3..5]);
+    }
+
+    #[test]
+    fn cursor_on_the_last_line_has_no_context_below() {
+        assert_eq!(viewport(10, 9, 20, Some(3), 2), [5..7, 7..10, 10..10]);
+    }
+
+    #[test]
+    fn window_follows_the_cursor_in_the_middle() {
+        assert_eq!(viewport(10, 5, 20, Some(3), 1), [3..4, 4..7, 7..8]);
+    }
+
+    #[test]
+    fn context_is_limited_by_the_text() {
+        assert_eq!(viewport(3, 1, 20, Some(1), 10), [0..1, 1..2, 2..3]);
+    }
+
+    #[test]
+    fn whole_text_is_shown_without_a_line_limit() {
+        assert_eq!(viewport(5, 4, 20, None, 2), [0..0, 0..5, 5..5]);
+    }
+
+    #[test]
+    fn short_screens_leave_no_room_for_context() {
+        assert_eq!(viewport(10, 5, 4, None, 2), [4..4, 4..8, 8..8]);
+    }
+}